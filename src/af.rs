@@ -0,0 +1,183 @@
+//! Alternate function mapping
+//!
+//! Single source of truth for which pin can be routed to which peripheral
+//! signal, transcribed from the "Alternate functions" tables of the
+//! STM32F302xB/C/D/E and STM32F303xB/C/D/E datasheets. Each row implements
+//! the corresponding marker trait (`serial::PinTx`, `spi::PinSck`,
+//! `i2c::PinScl`, `pwm::PinCh`, ...) which the drivers use to accept pins.
+//!
+//! There is one table per device variant: the rows shared by the xB/C and
+//! xD/E parts, and the rows only found on xD/E parts (`xde` feature). Rows
+//! for pins that are not bonded out on smaller packages carry the same
+//! package `cfg` as the pin in the `gpio` module. The pins only bonded out on
+//! LQFP-144 (PF3, PF5, PF7, PF8, PF11 to PF15, GPIOG and GPIOH) only carry
+//! FMC and other signals that no driver supports yet, so they have no rows.
+
+use crate::gpio::*;
+use crate::i2c::{PinScl, PinSda};
+use crate::pac::{I2C1, I2C2};
+use crate::pac::{SPI1, SPI2, SPI3};
+use crate::pac::{TIM2, TIM3, TIM4};
+use crate::pac::{USART1, USART2, USART3};
+use crate::pwm::PinCh;
+use crate::serial::{PinRx, PinTx};
+use crate::spi::{PinMiso, PinMosi, PinSck};
+use crate::timer::{CH1, CH2, CH3, CH4};
+
+macro_rules! af_table {
+    ($($(#[$attr:meta])* $PXi:ident: $AFN:ident => $Trait:path,)+) => {
+        $(
+            $(#[$attr])*
+            impl<PT: PullType, PM: PinMode> $Trait for $PXi<PT, PM> {
+                type AF = $AFN;
+            }
+        )+
+    };
+}
+
+// STM32F302xB/C/D/E and STM32F303xB/C/D/E
+#[cfg(any(feature = "stm32f302", feature = "stm32f303"))]
+af_table! {
+    PA0: AF1 => PinCh<TIM2, CH1>,
+    PA1: AF1 => PinCh<TIM2, CH2>,
+    PA2: AF1 => PinCh<TIM2, CH3>,
+    PA2: AF7 => PinTx<USART2>,
+    PA3: AF1 => PinCh<TIM2, CH4>,
+    PA3: AF7 => PinRx<USART2>,
+    PA4: AF2 => PinCh<TIM3, CH2>,
+    PA5: AF1 => PinCh<TIM2, CH1>,
+    PA5: AF5 => PinSck<SPI1>,
+    PA6: AF2 => PinCh<TIM3, CH1>,
+    PA6: AF5 => PinMiso<SPI1>,
+    PA7: AF2 => PinCh<TIM3, CH2>,
+    PA7: AF5 => PinMosi<SPI1>,
+    PA9: AF4 => PinScl<I2C2>,
+    PA9: AF7 => PinTx<USART1>,
+    PA9: AF10 => PinCh<TIM2, CH3>,
+    PA10: AF4 => PinSda<I2C2>,
+    PA10: AF7 => PinRx<USART1>,
+    PA10: AF10 => PinCh<TIM2, CH4>,
+    PA11: AF10 => PinCh<TIM4, CH1>,
+    PA12: AF10 => PinCh<TIM4, CH2>,
+    PA13: AF10 => PinCh<TIM4, CH3>,
+    PA14: AF4 => PinSda<I2C1>,
+    PA14: AF7 => PinTx<USART2>,
+    PA15: AF1 => PinCh<TIM2, CH1>,
+    PA15: AF4 => PinScl<I2C1>,
+    PA15: AF7 => PinRx<USART2>,
+
+    PB0: AF2 => PinCh<TIM3, CH3>,
+    PB1: AF2 => PinCh<TIM3, CH4>,
+    PB3: AF1 => PinCh<TIM2, CH2>,
+    PB3: AF5 => PinSck<SPI1>,
+    PB3: AF6 => PinSck<SPI3>,
+    PB3: AF7 => PinTx<USART2>,
+    PB4: AF2 => PinCh<TIM3, CH1>,
+    PB4: AF5 => PinMiso<SPI1>,
+    PB4: AF6 => PinMiso<SPI3>,
+    PB4: AF7 => PinRx<USART2>,
+    PB5: AF2 => PinCh<TIM3, CH2>,
+    PB5: AF5 => PinMosi<SPI1>,
+    PB5: AF6 => PinMosi<SPI3>,
+    PB6: AF2 => PinCh<TIM4, CH1>,
+    PB6: AF4 => PinScl<I2C1>,
+    PB6: AF7 => PinTx<USART1>,
+    PB7: AF2 => PinCh<TIM4, CH2>,
+    PB7: AF4 => PinSda<I2C1>,
+    PB7: AF7 => PinRx<USART1>,
+    PB7: AF10 => PinCh<TIM3, CH4>,
+    PB8: AF2 => PinCh<TIM4, CH3>,
+    PB8: AF4 => PinScl<I2C1>,
+    PB9: AF2 => PinCh<TIM4, CH4>,
+    PB9: AF4 => PinSda<I2C1>,
+    PB10: AF1 => PinCh<TIM2, CH3>,
+    PB10: AF7 => PinTx<USART3>,
+    PB11: AF1 => PinCh<TIM2, CH4>,
+    PB11: AF7 => PinRx<USART3>,
+    PB13: AF5 => PinSck<SPI2>,
+    PB14: AF5 => PinMiso<SPI2>,
+    PB15: AF5 => PinMosi<SPI2>,
+
+    #[cfg(not(feature = "lqfp48"))]
+    PC4: AF7 => PinTx<USART1>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC5: AF7 => PinRx<USART1>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC6: AF2 => PinCh<TIM3, CH1>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC7: AF2 => PinCh<TIM3, CH2>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC8: AF2 => PinCh<TIM3, CH3>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC9: AF2 => PinCh<TIM3, CH4>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC10: AF6 => PinSck<SPI3>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC10: AF7 => PinTx<USART3>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC11: AF6 => PinMiso<SPI3>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC11: AF7 => PinRx<USART3>,
+    #[cfg(not(feature = "lqfp48"))]
+    PC12: AF6 => PinMosi<SPI3>,
+
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD3: AF2 => PinCh<TIM2, CH1>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD4: AF2 => PinCh<TIM2, CH2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD5: AF7 => PinTx<USART2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD6: AF2 => PinCh<TIM2, CH4>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD6: AF7 => PinRx<USART2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD7: AF2 => PinCh<TIM2, CH3>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD8: AF7 => PinTx<USART3>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD9: AF7 => PinRx<USART3>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD12: AF2 => PinCh<TIM4, CH1>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD13: AF2 => PinCh<TIM4, CH2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD14: AF2 => PinCh<TIM4, CH3>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PD15: AF2 => PinCh<TIM4, CH4>,
+
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE0: AF7 => PinTx<USART1>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE1: AF7 => PinRx<USART1>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE2: AF2 => PinCh<TIM3, CH1>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE3: AF2 => PinCh<TIM3, CH2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE4: AF2 => PinCh<TIM3, CH3>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE5: AF2 => PinCh<TIM3, CH4>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PE15: AF7 => PinRx<USART3>,
+
+    PF0: AF4 => PinSda<I2C2>,
+    PF1: AF4 => PinScl<I2C2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PF6: AF2 => PinCh<TIM4, CH4>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PF6: AF4 => PinScl<I2C2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PF9: AF5 => PinSck<SPI2>,
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+    PF10: AF5 => PinSck<SPI2>,
+}
+
+// STM32F302xD/E and STM32F303xD/E only
+#[cfg(all(any(feature = "stm32f302", feature = "stm32f303"),
+          feature = "xde"))]
+af_table! {
+    PA10: AF5 => PinMiso<SPI2>,
+    PA11: AF5 => PinMosi<SPI2>,
+    PF1: AF5 => PinSck<SPI2>,
+}
@@ -21,6 +21,11 @@ impl Delay {
         Delay { syst, clocks }
     }
 
+    /// Re-times the delays after the clock tree was reconfigured
+    pub fn reclock(&mut self, clocks: &Clocks) {
+        self.clocks = *clocks;
+    }
+
     /// Releases the system timer (SysTick) resource
     pub fn free(self) -> SYST {
         self.syst
@@ -127,7 +127,7 @@ pub struct R;
 pub struct W;
 
 macro_rules! dma {
-    ($($DMAX:ident: ($dmaX:ident, $dmaXen:ident, $dmaXrst:ident, {
+    ($($DMAX:ident: ($dmaX:ident, {
         $($CX:ident: (
             $chX:ident,
             $htifX:ident,
@@ -145,7 +145,7 @@ macro_rules! dma {
                 use crate::pac::{$DMAX, dma1};
 
                 use crate::dma::{CircBuffer, DmaExt, Error, Event, Half, Transfer, W};
-                use crate::rcc::AHB;
+                use crate::rcc::{Enable, AHB};
 
                 /// Channels
                 pub struct Channels((), $(pub $CX),+);
@@ -314,7 +314,7 @@ macro_rules! dma {
                     type Channels = Channels;
 
                     fn split(self, ahb: &mut AHB) -> Channels {
-                        ahb.enr().modify(|_, w| w.$dmaXen().enabled());
+                        $DMAX::enable(ahb);
 
                         // reset the DMA control registers (stops all on-going transfers)
                         $(
@@ -330,7 +330,7 @@ macro_rules! dma {
 }
 
 dma! {
-    DMA1: (dma1, dma1en, dma1rst, {
+    DMA1: (dma1, {
         C1: (
             ch1,
             htif1, tcif1,
@@ -368,7 +368,7 @@ dma! {
         ),
     }),
 
-    DMA2: (dma2, dma2en, dma2rst, {
+    DMA2: (dma2, {
         C1: (
             ch1,
             htif1, tcif1,
@@ -0,0 +1,815 @@
+//! EEPROM emulation on top of flash pages
+//!
+//! Stores 32-bit values under 16-bit keys in two or more dedicated flash
+//! pages. Updates are appended to the active page, which is only erased once
+//! it is full: the latest value of every key is then copied to the next page
+//! of the ring, so erase cycles are spread evenly over all the pages.
+//!
+//! Every step of the page transfer leaves the pages in a state that
+//! [`Eeprom::new`] recovers from if power is lost, and a record only counts
+//! once it has been completely programmed.
+//!
+//! The layer only relies on the [`Storage`] trait, implemented by
+//! `flash::FlashWriter` and by [`MemStorage`], which follows the flash
+//! programming rules in RAM so the layer can be exercised on a host.
+//!
+//! [`Eeprom::new`]: struct.Eeprom.html#method.new
+//! [`Storage`]: trait.Storage.html
+//! [`MemStorage`]: struct.MemStorage.html
+
+/// Flash-like storage: erased to `0xFFFF` one page at a time and programmed
+/// one half-word at a time
+pub trait Storage {
+    /// Storage error
+    type Error;
+
+    /// Returns the size of the storage in bytes
+    fn size(&self) -> u32;
+
+    /// Returns the size of an erasable page in bytes
+    fn page_size(&self) -> u32;
+
+    /// Reads the half-word at `offset`
+    fn read(&self, offset: u32) -> u16;
+
+    /// Erases the page starting at `offset`
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error>;
+
+    /// Programs the half-word at `offset`, which must be erased unless
+    /// `half_word` is 0
+    fn program(&mut self,
+               offset: u32,
+               half_word: u16)
+               -> Result<(), Self::Error>;
+}
+
+/// EEPROM emulation error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error<E> {
+    /// The storage failed
+    Storage(E),
+    /// The active page is full of distinct keys
+    Full,
+    /// `0xFFFF` is reserved and can't be used as a key
+    InvalidKey,
+    /// The pages are in a state that no power loss can explain
+    Corrupted,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Storage(e)
+    }
+}
+
+// Page header, 8 half-words:
+// status, retired, generation (lo, hi), !generation (lo, hi), 2 x reserved
+const HEADER: u32 = 16;
+const STATUS: u32 = 0;
+const RETIRED: u32 = 2;
+const GENERATION: u32 = 4;
+const NOT_GENERATION: u32 = 8;
+
+const ERASED: u16 = 0xFFFF;
+const RECEIVING: u16 = 0xEEEE;
+const ACTIVE: u16 = 0x0000;
+
+// Record, 4 half-words: key, value (lo, hi), commit
+const RECORD: u32 = 8;
+const KEY: u32 = 0;
+const VALUE: u32 = 2;
+const COMMIT: u32 = 6;
+
+const COMMITTED: u16 = 0x0000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum PageState {
+    Erased,
+    /// Being filled by a transfer, or being promoted to active
+    Receiving(u32),
+    Active(u32),
+    /// Retired or partially programmed or erased
+    Invalid,
+}
+
+/// Returns `true` if generation `a` is newer than `b`
+fn newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Key/value store emulating an EEPROM over flash pages
+pub struct Eeprom<S: Storage> {
+    storage: S,
+    first_page: u32,
+    pages: u32,
+    /// Index of the active page
+    active: u32,
+    /// Generation of the active page
+    generation: u32,
+    /// Offset of the first free record in the active page
+    next: u32,
+}
+
+impl<S: Storage> Eeprom<S> {
+    /// Opens the store over `pages` pages of `storage` starting at page
+    /// number `first_page`
+    ///
+    /// Recovers from an interrupted page transfer, and formats the pages if
+    /// none of them holds a store.
+    ///
+    /// Panics if less than 2 pages are given or if they don't fit in the
+    /// storage
+    pub fn new(storage: S,
+               first_page: u32,
+               pages: u32)
+               -> Result<Self, Error<S::Error>> {
+        assert!(pages >= 2);
+        assert!((first_page + pages) * storage.page_size() <= storage.size());
+
+        let mut eeprom = Eeprom { storage,
+                                  first_page,
+                                  pages,
+                                  active: 0,
+                                  generation: 0,
+                                  next: HEADER };
+        eeprom.recover()?;
+
+        Ok(eeprom)
+    }
+
+    /// Returns the latest value stored under `key`
+    pub fn read(&self, key: u16) -> Option<u32> {
+        let page = self.page_offset(self.active);
+        self.find(page, HEADER, self.next, key)
+    }
+
+    /// Stores `value` under `key`
+    ///
+    /// Nothing is programmed if the value is unchanged. Transfers the
+    /// latest values to the next page first if the active page is full.
+    pub fn write(&mut self,
+                 key: u16,
+                 value: u32)
+                 -> Result<(), Error<S::Error>> {
+        if key == ERASED {
+            return Err(Error::InvalidKey);
+        }
+        if self.read(key) == Some(value) {
+            return Ok(());
+        }
+        if self.next + RECORD > self.storage.page_size() {
+            self.transfer()?;
+            if self.next + RECORD > self.storage.page_size() {
+                return Err(Error::Full);
+            }
+        }
+
+        let record = self.page_offset(self.active) + self.next;
+        // a partially programmed record is skipped from now on
+        self.next += RECORD;
+        self.program_record(record, key, value)
+    }
+
+    /// Erases every page and starts an empty store
+    pub fn format(&mut self) -> Result<(), Error<S::Error>> {
+        for page in 0..self.pages {
+            self.erase(page)?;
+        }
+        self.open(0, 0)
+    }
+
+    /// Releases the storage
+    pub fn free(self) -> S {
+        self.storage
+    }
+
+    fn recover(&mut self) -> Result<(), Error<S::Error>> {
+        let mut active: Option<(u32, u32)> = None;
+        let mut receiving: Option<(u32, u32)> = None;
+        for page in 0..self.pages {
+            match self.page_state(page) {
+                PageState::Erased => {}
+                PageState::Active(generation) => {
+                    // the page retired by a transfer may look active if its
+                    // erase was interrupted, the newer one wins
+                    match active {
+                        Some((old, old_generation))
+                            if newer(generation, old_generation) =>
+                        {
+                            self.erase(old)?;
+                            active = Some((page, generation));
+                        }
+                        Some(_) => self.erase(page)?,
+                        None => active = Some((page, generation)),
+                    }
+                }
+                PageState::Receiving(generation) => {
+                    if receiving.replace((page, generation)).is_some() {
+                        return Err(Error::Corrupted);
+                    }
+                }
+                PageState::Invalid => self.erase(page)?,
+            }
+        }
+
+        match (active, receiving) {
+            (Some((page, generation)), receiving) => {
+                // the transfer didn't complete, the active page is intact
+                if let Some((receiving, _)) = receiving {
+                    self.erase(receiving)?;
+                }
+                self.active = page;
+                self.generation = generation;
+                self.next = self.free_offset(page);
+                Ok(())
+            }
+            (None, Some((page, generation))) => {
+                // the transfer completed and the old page was retired
+                self.storage
+                    .program(self.page_offset(page) + STATUS, ACTIVE)?;
+                self.active = page;
+                self.generation = generation;
+                self.next = self.free_offset(page);
+                Ok(())
+            }
+            (None, None) => self.open(0, 0),
+        }
+    }
+
+    /// Copies the latest value of every key to the next page of the ring
+    fn transfer(&mut self) -> Result<(), Error<S::Error>> {
+        let old = self.page_offset(self.active);
+        let new_page = (self.active + 1) % self.pages;
+        let new = self.page_offset(new_page);
+        let generation = self.generation.wrapping_add(1);
+
+        if !self.is_blank(new_page) {
+            self.erase(new_page)?;
+        }
+        self.program_generation(new, generation)?;
+        self.storage.program(new + STATUS, RECEIVING)?;
+
+        let mut next = HEADER;
+        let mut record = HEADER;
+        while record < self.next {
+            if let Some((key, value)) = self.record(old + record) {
+                // only copy the latest value of each key
+                if self.find(old, record + RECORD, self.next, key).is_none() {
+                    self.program_record(new + next, key, value)?;
+                    next += RECORD;
+                }
+            }
+            record += RECORD;
+        }
+
+        self.storage.program(old + RETIRED, 0)?;
+        self.storage.program(new + STATUS, ACTIVE)?;
+        let old_page = self.active;
+        self.active = new_page;
+        self.generation = generation;
+        self.next = next;
+
+        self.erase(old_page)
+    }
+
+    /// Makes the blank or erased `page` the active one
+    fn open(&mut self,
+            page: u32,
+            generation: u32)
+            -> Result<(), Error<S::Error>> {
+        if !self.is_blank(page) {
+            self.erase(page)?;
+        }
+        let offset = self.page_offset(page);
+        self.program_generation(offset, generation)?;
+        self.storage.program(offset + STATUS, ACTIVE)?;
+        self.active = page;
+        self.generation = generation;
+        self.next = HEADER;
+
+        Ok(())
+    }
+
+    fn page_state(&self, page: u32) -> PageState {
+        let offset = self.page_offset(page);
+        let status = self.storage.read(offset + STATUS);
+        if self.storage.read(offset + RETIRED) != ERASED {
+            return PageState::Invalid;
+        }
+        if status == ERASED {
+            let blank = (0..HEADER).step_by(2)
+                                   .all(|h| self.storage.read(offset + h)
+                                            == ERASED);
+            return if blank {
+                       PageState::Erased
+                   } else {
+                       PageState::Invalid
+                   };
+        }
+
+        let generation = self.read_u32(offset + GENERATION);
+        if self.read_u32(offset + NOT_GENERATION) != !generation {
+            PageState::Invalid
+        } else if status == ACTIVE {
+            PageState::Active(generation)
+        } else if status & !RECEIVING == 0 {
+            // RECEIVING, possibly partially programmed to ACTIVE
+            PageState::Receiving(generation)
+        } else {
+            PageState::Invalid
+        }
+    }
+
+    /// Returns the offset following the last non blank record of `page`
+    fn free_offset(&self, page: u32) -> u32 {
+        let offset = self.page_offset(page);
+        let mut next = self.storage.page_size() / RECORD * RECORD;
+        while next > HEADER
+              && (0..RECORD).step_by(2)
+                            .all(|h| {
+                                     self.storage.read(offset + next - RECORD
+                                                       + h)
+                                     == ERASED
+                                 })
+        {
+            next -= RECORD;
+        }
+        next
+    }
+
+    /// Returns the value of the last committed record of `key` in
+    /// `start..end` of the page at `page`
+    fn find(&self, page: u32, start: u32, end: u32, key: u16) -> Option<u32> {
+        let mut record = end;
+        while record >= start + RECORD {
+            record -= RECORD;
+            match self.record(page + record) {
+                Some((k, value)) if k == key => return Some(value),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn record(&self, offset: u32) -> Option<(u16, u32)> {
+        let key = self.storage.read(offset + KEY);
+        if self.storage.read(offset + COMMIT) == COMMITTED && key != ERASED {
+            Some((key, self.read_u32(offset + VALUE)))
+        } else {
+            None
+        }
+    }
+
+    fn program_record(&mut self,
+                      offset: u32,
+                      key: u16,
+                      value: u32)
+                      -> Result<(), Error<S::Error>> {
+        self.storage.program(offset + KEY, key)?;
+        self.program_u32(offset + VALUE, value)?;
+        // the record only counts once the commit is programmed
+        self.storage.program(offset + COMMIT, COMMITTED)?;
+        Ok(())
+    }
+
+    fn program_generation(&mut self,
+                          offset: u32,
+                          generation: u32)
+                          -> Result<(), Error<S::Error>> {
+        self.program_u32(offset + GENERATION, generation)?;
+        self.program_u32(offset + NOT_GENERATION, !generation)
+    }
+
+    fn program_u32(&mut self,
+                   offset: u32,
+                   value: u32)
+                   -> Result<(), Error<S::Error>> {
+        self.storage.program(offset, value as u16)?;
+        self.storage.program(offset + 2, (value >> 16) as u16)?;
+        Ok(())
+    }
+
+    fn read_u32(&self, offset: u32) -> u32 {
+        u32::from(self.storage.read(offset))
+        | u32::from(self.storage.read(offset + 2)) << 16
+    }
+
+    fn is_blank(&self, page: u32) -> bool {
+        let offset = self.page_offset(page);
+        (0..self.storage.page_size()).step_by(2)
+                                     .all(|h| {
+                                              self.storage.read(offset + h)
+                                              == ERASED
+                                          })
+    }
+
+    fn erase(&mut self, page: u32) -> Result<(), Error<S::Error>> {
+        let offset = self.page_offset(page);
+        self.storage.erase_page(offset)?;
+        Ok(())
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        (self.first_page + page) * self.storage.page_size()
+    }
+}
+
+/// In-memory storage error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemError {
+    /// A half-word that was not erased was programmed
+    NotErased,
+    /// The offset is outside of the memory or not aligned
+    OutOfBounds,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Storage in RAM that follows the flash erase and programming rules
+pub struct MemStorage<'a> {
+    mem: &'a mut [u16],
+    page_size: u32,
+}
+
+impl<'a> MemStorage<'a> {
+    /// Uses `mem` as storage made of pages of `page_size` bytes
+    ///
+    /// `mem` is left as is, i.e. it is not erased.
+    ///
+    /// Panics if `mem` is not made of whole pages
+    pub fn new(mem: &'a mut [u16], page_size: u32) -> Self {
+        assert!(page_size >= 2 && page_size % 2 == 0);
+        assert!(mem.len() as u32 * 2 % page_size == 0);
+
+        MemStorage { mem, page_size }
+    }
+
+    /// Releases the memory
+    pub fn free(self) -> &'a mut [u16] {
+        self.mem
+    }
+
+    fn index(&self, offset: u32) -> Result<usize, MemError> {
+        let index = (offset / 2) as usize;
+        if offset % 2 != 0 || index >= self.mem.len() {
+            Err(MemError::OutOfBounds)
+        } else {
+            Ok(index)
+        }
+    }
+}
+
+impl<'a> Storage for MemStorage<'a> {
+    type Error = MemError;
+
+    fn size(&self) -> u32 {
+        self.mem.len() as u32 * 2
+    }
+
+    fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    fn read(&self, offset: u32) -> u16 {
+        self.mem[(offset / 2) as usize]
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), MemError> {
+        if offset % self.page_size != 0 {
+            return Err(MemError::OutOfBounds);
+        }
+        let start = self.index(offset)?;
+        let end = start + (self.page_size / 2) as usize;
+        for half_word in &mut self.mem[start..end] {
+            *half_word = ERASED;
+        }
+        Ok(())
+    }
+
+    fn program(&mut self, offset: u32, half_word: u16) -> Result<(), MemError> {
+        let index = self.index(offset)?;
+        if self.mem[index] != ERASED && half_word != 0 {
+            return Err(MemError::NotErased);
+        }
+        self.mem[index] = half_word;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: u32 = 128;
+    const PAGES: u32 = 3;
+    const WORDS: usize = (PAGE * PAGES / 2) as usize;
+    const KEYS: usize = 6;
+    /// Records that fit in a page
+    const SLOTS: u32 = (PAGE - HEADER) / RECORD;
+
+    #[derive(Debug, PartialEq)]
+    enum LossError {
+        Lost,
+        Mem(MemError),
+    }
+
+    /// Storage that loses power after `budget` erases and programs, the
+    /// interrupted operation is left half done
+    struct Lossy<'a> {
+        mem: MemStorage<'a>,
+        budget: u32,
+        /// An interrupted erase only erases the first half of the page,
+        /// otherwise the second half
+        first_half: bool,
+    }
+
+    impl<'a> Storage for Lossy<'a> {
+        type Error = LossError;
+
+        fn size(&self) -> u32 {
+            self.mem.size()
+        }
+
+        fn page_size(&self) -> u32 {
+            self.mem.page_size()
+        }
+
+        fn read(&self, offset: u32) -> u16 {
+            self.mem.read(offset)
+        }
+
+        fn erase_page(&mut self, offset: u32) -> Result<(), LossError> {
+            if self.budget == 0 {
+                let half = PAGE / 2;
+                let start = if self.first_half { 0 } else { half };
+                for h in (start..start + half).step_by(2) {
+                    self.mem.mem[((offset + h) / 2) as usize] = ERASED;
+                }
+                return Err(LossError::Lost);
+            }
+            self.budget -= 1;
+            self.mem.erase_page(offset).map_err(LossError::Mem)
+        }
+
+        fn program(&mut self,
+                   offset: u32,
+                   half_word: u16)
+                   -> Result<(), LossError> {
+            if self.budget == 0 {
+                // only some of the bits are cleared
+                self.mem.mem[(offset / 2) as usize] &= half_word | 0xA5A5;
+                return Err(LossError::Lost);
+            }
+            self.budget -= 1;
+            self.mem.program(offset, half_word).map_err(LossError::Mem)
+        }
+    }
+
+    /// Storage that counts the erases of every page
+    struct Counting<'a> {
+        mem: MemStorage<'a>,
+        erases: [u32; 5],
+    }
+
+    impl<'a> Storage for Counting<'a> {
+        type Error = MemError;
+
+        fn size(&self) -> u32 {
+            self.mem.size()
+        }
+
+        fn page_size(&self) -> u32 {
+            self.mem.page_size()
+        }
+
+        fn read(&self, offset: u32) -> u16 {
+            self.mem.read(offset)
+        }
+
+        fn erase_page(&mut self, offset: u32) -> Result<(), MemError> {
+            self.erases[(offset / PAGE) as usize] += 1;
+            self.mem.erase_page(offset)
+        }
+
+        fn program(&mut self,
+                   offset: u32,
+                   half_word: u16)
+                   -> Result<(), MemError> {
+            self.mem.program(offset, half_word)
+        }
+    }
+
+    fn open(mem: &mut [u16]) -> Eeprom<MemStorage<'_>> {
+        Eeprom::new(MemStorage::new(mem, PAGE), 0, PAGES).unwrap()
+    }
+
+    /// Checks that the store holds `values`, indexed by key
+    fn check<S: Storage>(eeprom: &Eeprom<S>, values: &[Option<u32>]) {
+        for (key, &value) in values.iter().enumerate() {
+            assert_eq!(eeprom.read(key as u16), value, "key {}", key);
+        }
+    }
+
+    /// Writes values to a store opened at `generation`, loses power after
+    /// `budget` storage operations, then checks that the recovered store
+    /// holds the last committed value of every key
+    ///
+    /// Returns `false` if all the values were written before power was lost
+    fn lose_power(budget: u32, first_half: bool, generation: u32) -> bool {
+        let mut mem = [ERASED; WORDS];
+        open(&mut mem).open(0, generation).unwrap();
+
+        let mut committed = [None; KEYS];
+        let mut interrupted = None;
+        let storage = Lossy { mem: MemStorage::new(&mut mem, PAGE),
+                              budget,
+                              first_half };
+        let mut eeprom = Eeprom::new(storage, 0, PAGES).unwrap();
+        for value in 0..200 {
+            let key = value as usize * 5 % KEYS;
+            match eeprom.write(key as u16, value) {
+                Ok(()) => committed[key] = Some(value),
+                Err(Error::Storage(LossError::Lost)) => {
+                    interrupted = Some((key, value));
+                    break;
+                }
+                Err(e) => panic!("budget {}: {:?}", budget, e),
+            }
+        }
+
+        let mut eeprom = open(&mut mem);
+        for (key, &committed) in committed.iter().enumerate() {
+            let value = eeprom.read(key as u16);
+            match interrupted {
+                Some((k, v)) if k == key => {
+                    assert!(value == committed || value == Some(v),
+                            "budget {}: key {} is {:?}",
+                            budget,
+                            key,
+                            value)
+                }
+                _ => {
+                    assert_eq!(value,
+                               committed,
+                               "budget {}: key {}",
+                               budget,
+                               key)
+                }
+            }
+        }
+
+        // the recovered store keeps working
+        for key in 0..KEYS {
+            eeprom.write(key as u16, !(key as u32)).unwrap();
+        }
+        let eeprom = open(&mut mem);
+        for key in 0..KEYS {
+            assert_eq!(eeprom.read(key as u16), Some(!(key as u32)));
+        }
+
+        interrupted.is_some()
+    }
+
+    #[test]
+    fn power_loss() {
+        for &generation in &[0, u32::MAX - 3] {
+            for &first_half in &[false, true] {
+                let mut budget = 0;
+                while lose_power(budget, first_half, generation) {
+                    budget += 1;
+                }
+                // power was lost at every step of several transfers
+                assert!(budget > 200 * 4);
+            }
+        }
+    }
+
+    #[test]
+    fn read_back() {
+        let mut mem = [ERASED; WORDS];
+        let mut eeprom = open(&mut mem);
+        assert_eq!(eeprom.read(0), None);
+
+        let mut values = [None; KEYS];
+        for value in 0..500 {
+            let key = value as usize * 7 % KEYS;
+            eeprom.write(key as u16, value).unwrap();
+            values[key] = Some(value);
+            check(&eeprom, &values);
+        }
+
+        let eeprom = open(&mut mem);
+        check(&eeprom, &values);
+    }
+
+    #[test]
+    fn wear_rotation() {
+        // the store uses pages 1 to 4, page 0 belongs to someone else
+        let mut mem = [ERASED; (PAGE * 5 / 2) as usize];
+        for half_word in &mut mem[..(PAGE / 2) as usize] {
+            *half_word = 0;
+        }
+
+        let storage = Counting { mem: MemStorage::new(&mut mem, PAGE),
+                                 erases: [0; 5] };
+        let mut eeprom = Eeprom::new(storage, 1, 4).unwrap();
+        for value in 0..1000 {
+            eeprom.write((value % 3) as u16, value).unwrap();
+        }
+        let erases = eeprom.free().erases;
+
+        assert_eq!(erases[0], 0);
+        let min = erases[1..].iter().min().unwrap();
+        let max = erases[1..].iter().max().unwrap();
+        assert!(*min > 0 && max - min <= 1, "{:?}", erases);
+        assert!(mem[..(PAGE / 2) as usize].iter().all(|&h| h == 0));
+    }
+
+    #[test]
+    fn unchanged_value() {
+        let mut mem = [ERASED; WORDS];
+        let mut eeprom = open(&mut mem);
+        eeprom.write(1, 42).unwrap();
+        let next = eeprom.next;
+        eeprom.write(1, 42).unwrap();
+        assert_eq!(eeprom.next, next);
+    }
+
+    #[test]
+    fn full() {
+        let mut mem = [ERASED; WORDS];
+        let mut eeprom = open(&mut mem);
+        for key in 0..SLOTS {
+            eeprom.write(key as u16, key).unwrap();
+        }
+
+        assert_eq!(eeprom.write(SLOTS as u16, 0), Err(Error::Full));
+        // the transfer keeps every key, but leaves no room to update them
+        assert_eq!(eeprom.write(0, 1), Err(Error::Full));
+        assert_eq!(eeprom.write(0, 0), Ok(()));
+        for key in 0..SLOTS {
+            assert_eq!(eeprom.read(key as u16), Some(key));
+        }
+        assert_eq!(eeprom.read(SLOTS as u16), None);
+    }
+
+    #[test]
+    fn invalid_key() {
+        let mut mem = [ERASED; WORDS];
+        let mut eeprom = open(&mut mem);
+        let next = eeprom.next;
+
+        assert_eq!(eeprom.write(ERASED, 0), Err(Error::InvalidKey));
+        assert_eq!(eeprom.next, next);
+        assert_eq!(eeprom.read(ERASED), None);
+    }
+
+    #[test]
+    fn generation_wraparound() {
+        let mut mem = [ERASED; WORDS];
+        let mut eeprom = open(&mut mem);
+        let mut generation = u32::MAX - 1;
+        eeprom.open(0, generation).unwrap();
+
+        let mut values = [None; KEYS];
+        for value in 0..100 {
+            let key = value as usize % KEYS;
+            eeprom.write(key as u16, value).unwrap();
+            values[key] = Some(value);
+            if eeprom.generation != generation {
+                assert_eq!(eeprom.generation, generation.wrapping_add(1));
+                generation = eeprom.generation;
+            }
+        }
+        // wrapped past 0
+        assert!(generation > 0 && generation < 10);
+
+        let eeprom = open(&mut mem);
+        assert_eq!(eeprom.generation, generation);
+        check(&eeprom, &values);
+    }
+
+    #[test]
+    fn newer_active_page_wins() {
+        // a transfer whose retired page looks active again, both ways round
+        // the wraparound of the generation
+        for &(old, new) in &[(0, 1), (1, 0)] {
+            let mut mem = [ERASED; WORDS];
+            let mut eeprom = open(&mut mem);
+            eeprom.open(old, u32::MAX).unwrap();
+            eeprom.write(1, 1).unwrap();
+            eeprom.open(new, 0).unwrap();
+            eeprom.write(1, 2).unwrap();
+
+            let eeprom = open(&mut mem);
+            assert_eq!(eeprom.read(1), Some(2));
+            assert_eq!((eeprom.active, eeprom.generation), (new, 0));
+
+            let start = (old * PAGE / 2) as usize;
+            let end = start + (PAGE / 2) as usize;
+            assert!(mem[start..end].iter().all(|&h| h == ERASED));
+        }
+    }
+}
@@ -245,5 +245,6 @@ fn bits_of_gpio_group(group: &gpio::Group) -> u8 {
         gpio::Group::E => 0b0100,
         gpio::Group::F => 0b0101,
         gpio::Group::G => 0b0110,
+        gpio::Group::H => 0b0111,
     }
 }
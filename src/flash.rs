@@ -1,6 +1,12 @@
 //! Flash memory
 
+use core::{ptr, slice};
+
+use cortex_m::asm;
+
+use crate::eeprom::Storage;
 use crate::pac::{flash, FLASH};
+use crate::rcc::{self, Clocks};
 
 /// Extension trait to constrain the FLASH peripheral
 pub trait FlashExt {
@@ -11,7 +17,8 @@ pub trait FlashExt {
 
 impl FlashExt for FLASH {
     fn constrain(self) -> Parts {
-        Parts { acr: ACR { _0: () } }
+        Parts { acr: ACR { _0: () },
+                writer: FlashWriter { _0: () } }
     }
 }
 
@@ -19,6 +26,8 @@ impl FlashExt for FLASH {
 pub struct Parts {
     /// Opaque ACR register
     pub acr: ACR,
+    /// Flash erase and programming
+    pub writer: FlashWriter,
 }
 
 /// Opaque ACR register
@@ -31,4 +40,584 @@ impl ACR {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*FLASH::ptr()).acr }
     }
+
+    /// Returns the number of wait states
+    pub fn latency(&self) -> Latency {
+        // NOTE(unsafe) atomic read with no side effects
+        match unsafe { (*FLASH::ptr()).acr.read().latency().bits() } {
+            0 => Latency::Ws0,
+            1 => Latency::Ws1,
+            _ => Latency::Ws2,
+        }
+    }
+
+    /// Returns `true` if the prefetch buffer is enabled
+    pub fn prefetch(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*FLASH::ptr()).acr.read().prftbs().bit_is_set() }
+    }
+
+    /// Returns `true` if half-cycle access is enabled
+    pub fn half_cycle(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*FLASH::ptr()).acr.read().bits() & HLFCYA != 0 }
+    }
+
+    /// Sets the number of wait states, which must be at least the one
+    /// required by the system clock
+    pub fn set_latency(&mut self,
+                       latency: Latency,
+                       clocks: &Clocks)
+                       -> Result<(), AcrError> {
+        if (latency as u8) < clocks.flash_latency() {
+            return Err(AcrError::LatencyTooLow);
+        }
+        self.acr()
+            .modify(|_, w| unsafe { w.latency().bits(latency as u8) });
+        Ok(())
+    }
+
+    /// Enables the prefetch buffer
+    ///
+    /// The prefetch buffer can only be toggled while the system clock is
+    /// 24 MHz or less and the AHB clock is not divided.
+    pub fn enable_prefetch(&mut self, clocks: &Clocks) -> Result<(), AcrError> {
+        self.set_prefetch(true, clocks)
+    }
+
+    /// Disables the prefetch buffer
+    ///
+    /// See [`enable_prefetch`](#method.enable_prefetch) for the conditions.
+    pub fn disable_prefetch(&mut self,
+                            clocks: &Clocks)
+                            -> Result<(), AcrError> {
+        self.set_prefetch(false, clocks)
+    }
+
+    /// Enables half-cycle access, which reduces the flash power consumption
+    ///
+    /// Only allowed while the system clock is 8 MHz or less, i.e. not
+    /// provided by the PLL, and with zero wait states.
+    pub fn enable_half_cycle(&mut self,
+                             clocks: &Clocks)
+                             -> Result<(), AcrError> {
+        if clocks.sysclk().0 > HALF_CYCLE_MAX
+           || self.latency() != Latency::Ws0
+        {
+            return Err(AcrError::HalfCycleUnavailable);
+        }
+        self.acr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | HLFCYA) });
+        Ok(())
+    }
+
+    /// Disables half-cycle access
+    pub fn disable_half_cycle(&mut self) {
+        self.acr()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !HLFCYA) });
+    }
+
+    /// Sets the latency and disables half-cycle access before the clock tree
+    /// is reconfigured, the prefetch buffer is kept on if `ahb_divided`
+    pub(crate) fn configure(&mut self, latency: u8, ahb_divided: bool) {
+        self.acr().modify(|r, w| unsafe {
+                      w.bits(r.bits() & !HLFCYA)
+                       .latency()
+                       .bits(latency)
+                       .prftbe()
+                       .bit(ahb_divided || r.prftbe().bit_is_set())
+                  });
+    }
+
+    fn set_prefetch(&mut self,
+                    enable: bool,
+                    clocks: &Clocks)
+                    -> Result<(), AcrError> {
+        if clocks.sysclk().0 > PREFETCH_TOGGLE_MAX
+           || clocks.hclk().0 != clocks.sysclk().0
+        {
+            return Err(AcrError::PrefetchLocked);
+        }
+        self.acr().modify(|_, w| w.prftbe().bit(enable));
+        Ok(())
+    }
+}
+
+/// Flash wait states
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    /// Zero wait states, for a system clock up to 24 MHz
+    Ws0 = 0,
+    /// One wait state, for a system clock up to 48 MHz
+    Ws1 = 1,
+    /// Two wait states, for a system clock up to 72 MHz
+    Ws2 = 2,
+}
+
+/// Flash access control error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcrError {
+    /// The latency is lower than required by the system clock
+    LatencyTooLow,
+    /// The prefetch buffer can't be toggled with the current clocks
+    PrefetchLocked,
+    /// Half-cycle access is not allowed with the current clocks or latency
+    HalfCycleUnavailable,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Start address of the main flash memory
+const FLASH_START: u32 = 0x0800_0000;
+/// Address of the flash size data register, in KiB
+const FLASH_SIZE: *const u16 = 0x1FFF_F7CC as *const u16;
+/// Size of a flash page in bytes
+pub const PAGE_SIZE: u32 = 2048;
+
+/// Half-cycle access enable bit of ACR
+const HLFCYA: u32 = 1 << 3;
+/// Maximum system clock for half-cycle access
+const HALF_CYCLE_MAX: u32 = 8_000_000;
+/// Maximum system clock for toggling the prefetch buffer
+const PREFETCH_TOGGLE_MAX: u32 = 24_000_000;
+
+/// Address of the option bytes, each stored in a half-word with its
+/// complement
+const OPTION_BYTES: u32 = 0x1FFF_F800;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+const RDP_LEVEL0: u8 = 0xAA;
+const RDP_LEVEL1: u8 = 0xBB;
+const RDP_LEVEL2: u8 = 0xCC;
+
+/// Flash erase and programming error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// A half-word that was not erased was programmed (PGERR)
+    Programming,
+    /// The address is write protected (WRPRTERR)
+    WriteProtection,
+    /// The half-word read back differs from the one programmed
+    Verify,
+    /// The address range is outside of the flash memory
+    OutOfBounds,
+    /// The offset is not aligned to a page or a half-word
+    Unaligned,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Option bytes programming error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionBytesError {
+    /// Erasing or programming the option bytes failed
+    Flash(Error),
+    /// Read protection level 2 was requested without `Level2::Confirm`
+    Level2NotConfirmed,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<Error> for OptionBytesError {
+    fn from(e: Error) -> Self {
+        OptionBytesError::Flash(e)
+    }
+}
+
+/// Flash erase and programming
+///
+/// All offsets are relative to the start of the flash memory.
+pub struct FlashWriter {
+    _0: (),
+}
+
+impl FlashWriter {
+    /// Returns the size of the flash memory in bytes
+    pub fn size(&self) -> u32 {
+        // NOTE(unsafe) read-only factory data
+        u32::from(unsafe { ptr::read_volatile(FLASH_SIZE) }) * 1024
+    }
+
+    /// Reads `len` bytes at `offset`
+    pub fn read(&self, offset: u32, len: u32) -> Result<&[u8], Error> {
+        self.check_bounds(offset, len)?;
+        // NOTE(unsafe) the range is within the memory mapped flash
+        Ok(unsafe {
+               slice::from_raw_parts((FLASH_START + offset) as *const u8,
+                                     len as usize)
+           })
+    }
+
+    /// Unlocks the flash for erasing and programming until the returned
+    /// value is dropped
+    ///
+    /// The HSI clocks the flash programming interface: it is turned on if
+    /// `ClockPlan::apply` turned it off, and is left on afterwards. Applying
+    /// a clock tree that uses the HSE turns it off again.
+    pub fn unlock(&mut self) -> UnlockedFlash<'_> {
+        rcc::enable_hsi();
+
+        let flash = self.flash();
+        if flash.cr.read().lock().bit_is_set() {
+            flash.keyr.write(|w| unsafe { w.fkeyr().bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.fkeyr().bits(KEY2) });
+        }
+
+        UnlockedFlash { writer: self }
+    }
+
+    /// Returns the option bytes loaded at the last reset or reload
+    pub fn option_bytes(&self) -> OptionBytes {
+        // NOTE(unsafe) atomic reads of read-only registers
+        let flash = unsafe { &*FLASH::ptr() };
+        let obr = flash.obr.read();
+
+        let read_protection = if obr.level2_prot().bit_is_set() {
+            ReadProtection::Level2
+        } else if obr.level1_prot().bit_is_set() {
+            ReadProtection::Level1
+        } else {
+            ReadProtection::Level0
+        };
+        let watchdog = if obr.wdg_sw().bit_is_set() {
+            WatchdogMode::Software
+        } else {
+            WatchdogMode::Hardware
+        };
+
+        OptionBytes { read_protection,
+                      watchdog,
+                      reset_on_stop: obr.n_rst_stop().bit_is_clear(),
+                      reset_on_standby: obr.n_rst_stdby().bit_is_clear(),
+                      n_boot1: obr.boot1().bit_is_set(),
+                      vdda_monitor: obr.vdda_monitor().bit_is_set(),
+                      sram_parity_check: obr.sram_parity_check()
+                                            .bit_is_clear(),
+                      data: [obr.data0().bits(), obr.data1().bits()],
+                      write_protection: !flash.wrpr.read().wrp().bits() }
+    }
+
+    /// Erases and programs the option bytes
+    ///
+    /// The new option bytes take effect after
+    /// [`reload_option_bytes`](#method.reload_option_bytes) or the next
+    /// power-on reset. Read protection level 2 is irreversible and is refused
+    /// unless `level2` is `Level2::Confirm`. Going back from level 1 to level
+    /// 0 mass erases the flash when the option bytes are reloaded.
+    pub fn program_option_bytes(&mut self,
+                                option_bytes: &OptionBytes,
+                                level2: Level2)
+                                -> Result<(), OptionBytesError> {
+        let rdp = match (option_bytes.read_protection, level2) {
+            (ReadProtection::Level0, _) => RDP_LEVEL0,
+            (ReadProtection::Level1, _) => RDP_LEVEL1,
+            (ReadProtection::Level2, Level2::Confirm) => RDP_LEVEL2,
+            (ReadProtection::Level2, Level2::Refuse) => {
+                return Err(OptionBytesError::Level2NotConfirmed)
+            }
+        };
+        let wrp = (!option_bytes.write_protection).to_le_bytes();
+        let bytes = [rdp,
+                     option_bytes.user(),
+                     option_bytes.data[0],
+                     option_bytes.data[1],
+                     wrp[0],
+                     wrp[1],
+                     wrp[2],
+                     wrp[3]];
+
+        self.unlock().program_option_bytes(&bytes)?;
+        Ok(())
+    }
+
+    /// Reloads the option bytes, which resets the device
+    pub fn reload_option_bytes(&mut self) -> ! {
+        let mut flash = self.unlock();
+        flash.unlock_option_bytes();
+        flash.writer
+             .flash()
+             .cr
+             .modify(|_, w| w.force_optload().set_bit());
+        loop {
+            asm::nop();
+        }
+    }
+
+    fn check_bounds(&self, offset: u32, len: u32) -> Result<(), Error> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    fn flash(&mut self) -> &flash::RegisterBlock {
+        // NOTE(unsafe) this proxy grants exclusive access to the KEYR, SR, CR
+        // and AR registers
+        unsafe { &*FLASH::ptr() }
+    }
+}
+
+/// Unlocked flash, locked again when dropped
+pub struct UnlockedFlash<'a> {
+    writer: &'a mut FlashWriter,
+}
+
+impl<'a> UnlockedFlash<'a> {
+    /// Erases the page starting at `offset`
+    pub fn erase_page(&mut self, offset: u32) -> Result<(), Error> {
+        if offset % PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.writer.check_bounds(offset, PAGE_SIZE)?;
+
+        let flash = self.writer.flash();
+        flash.cr.modify(|_, w| w.per().set_bit());
+        flash.ar.write(|w| unsafe { w.far().bits(FLASH_START + offset) });
+        flash.cr.modify(|_, w| w.strt().set_bit());
+        let result = self.wait();
+        self.writer.flash().cr.modify(|_, w| w.per().clear_bit());
+
+        result
+    }
+
+    /// Erases the whole flash memory
+    ///
+    /// # Safety
+    ///
+    /// This also erases the running program, the caller must be executing
+    /// from RAM and must not return to code in flash.
+    pub unsafe fn erase_all(&mut self) -> Result<(), Error> {
+        let flash = self.writer.flash();
+        flash.cr.modify(|_, w| w.mer().set_bit());
+        flash.cr.modify(|_, w| w.strt().set_bit());
+        let result = self.wait();
+        self.writer.flash().cr.modify(|_, w| w.mer().clear_bit());
+
+        result
+    }
+
+    /// Programs `data` at `offset` one half-word at a time and verifies it
+    ///
+    /// The target half-words must be erased, except when programming 0.
+    pub fn program(&mut self, offset: u32, data: &[u16]) -> Result<(), Error> {
+        if offset % 2 != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.writer.check_bounds(offset, data.len() as u32 * 2)?;
+
+        self.writer.flash().cr.modify(|_, w| w.pg().set_bit());
+        let result = data.iter()
+                         .zip((offset..).step_by(2))
+                         .map(|(&half_word, offset)| {
+                             self.program_half_word(offset, half_word)
+                         })
+                         .find(Result::is_err)
+                         .unwrap_or(Ok(()));
+        self.writer.flash().cr.modify(|_, w| w.pg().clear_bit());
+
+        result
+    }
+
+    fn program_half_word(&mut self,
+                         offset: u32,
+                         half_word: u16)
+                         -> Result<(), Error> {
+        let address = (FLASH_START + offset) as *mut u16;
+        // NOTE(unsafe) the address was checked against the flash size
+        unsafe { ptr::write_volatile(address, half_word) };
+        self.wait()?;
+
+        if unsafe { ptr::read_volatile(address) } != half_word {
+            Err(Error::Verify)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn unlock_option_bytes(&mut self) {
+        let flash = self.writer.flash();
+        if flash.cr.read().optwre().bit_is_clear() {
+            flash.optkeyr.write(|w| unsafe { w.optkeyr().bits(KEY1) });
+            flash.optkeyr.write(|w| unsafe { w.optkeyr().bits(KEY2) });
+        }
+    }
+
+    fn program_option_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.unlock_option_bytes();
+
+        self.writer.flash().cr.modify(|_, w| w.opter().set_bit());
+        self.writer.flash().cr.modify(|_, w| w.strt().set_bit());
+        let result = self.wait();
+        self.writer.flash().cr.modify(|_, w| w.opter().clear_bit());
+        result?;
+
+        self.writer.flash().cr.modify(|_, w| w.optpg().set_bit());
+        let result = bytes.iter()
+                          .zip((OPTION_BYTES..).step_by(2))
+                          .map(|(&byte, address)| {
+                              self.program_option_byte(address, byte)
+                          })
+                          .find(Result::is_err)
+                          .unwrap_or(Ok(()));
+        self.writer
+            .flash()
+            .cr
+            .modify(|_, w| w.optpg().clear_bit().optwre().clear_bit());
+
+        result
+    }
+
+    fn program_option_byte(&mut self,
+                           address: u32,
+                           byte: u8)
+                           -> Result<(), Error> {
+        let address = address as *mut u16;
+        // NOTE(unsafe) the complement in the upper byte is computed by the
+        // hardware
+        unsafe { ptr::write_volatile(address, u16::from(byte)) };
+        self.wait()?;
+
+        if unsafe { ptr::read_volatile(address) } as u8 != byte {
+            Err(Error::Verify)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits for the ongoing operation and clears the status flags
+    fn wait(&mut self) -> Result<(), Error> {
+        let flash = self.writer.flash();
+        while flash.sr.read().bsy().bit_is_set() {}
+
+        let sr = flash.sr.read();
+        flash.sr.write(|w| {
+                     w.eop()
+                      .set_bit()
+                      .pgerr()
+                      .set_bit()
+                      .wrprt()
+                      .set_bit()
+                 });
+        if sr.pgerr().bit_is_set() {
+            Err(Error::Programming)
+        } else if sr.wrprt().bit_is_set() {
+            Err(Error::WriteProtection)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Drop for UnlockedFlash<'a> {
+    fn drop(&mut self) {
+        self.writer.flash().cr.modify(|_, w| w.lock().set_bit());
+    }
+}
+
+impl Storage for FlashWriter {
+    type Error = Error;
+
+    fn size(&self) -> u32 {
+        FlashWriter::size(self)
+    }
+
+    fn page_size(&self) -> u32 {
+        PAGE_SIZE
+    }
+
+    fn read(&self, offset: u32) -> u16 {
+        assert!(offset % 2 == 0 && offset < self.size());
+        // NOTE(unsafe) the address is within the memory mapped flash
+        unsafe { ptr::read_volatile((FLASH_START + offset) as *const u16) }
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), Error> {
+        self.unlock().erase_page(offset)
+    }
+
+    fn program(&mut self, offset: u32, half_word: u16) -> Result<(), Error> {
+        self.unlock().program(offset, &[half_word])
+    }
+}
+
+/// Option bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionBytes {
+    /// Read-out protection level
+    pub read_protection: ReadProtection,
+    /// Independent watchdog selection
+    pub watchdog: WatchdogMode,
+    /// A reset is generated instead of entering Stop mode (nRST_STOP)
+    pub reset_on_stop: bool,
+    /// A reset is generated instead of entering Standby mode (nRST_STDBY)
+    pub reset_on_standby: bool,
+    /// nBOOT1, selects the boot memory together with the BOOT0 pin
+    pub n_boot1: bool,
+    /// The VDDA power supply supervisor is enabled
+    pub vdda_monitor: bool,
+    /// The SRAM parity check is enabled
+    pub sram_parity_check: bool,
+    /// User data bytes
+    pub data: [u8; 2],
+    /// Write-protected page groups, bit `n` protects the pages `2n` and
+    /// `2n + 1`
+    pub write_protection: u32,
+}
+
+impl OptionBytes {
+    fn user(&self) -> u8 {
+        // reserved bits 3 and 7 are kept set
+        let mut user = 0b1000_1000;
+        if self.watchdog == WatchdogMode::Software {
+            user |= 1 << 0;
+        }
+        if !self.reset_on_stop {
+            user |= 1 << 1;
+        }
+        if !self.reset_on_standby {
+            user |= 1 << 2;
+        }
+        if self.n_boot1 {
+            user |= 1 << 4;
+        }
+        if self.vdda_monitor {
+            user |= 1 << 5;
+        }
+        if !self.sram_parity_check {
+            user |= 1 << 6;
+        }
+        user
+    }
+}
+
+/// Read-out protection level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadProtection {
+    /// No protection
+    Level0,
+    /// Read protection of the flash memory while a debugger is connected or
+    /// when booting from RAM or system memory
+    Level1,
+    /// Level 1 protection with the debug interface permanently disabled
+    Level2,
+}
+
+/// Independent watchdog selection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogMode {
+    /// The watchdog is started by hardware at reset
+    Hardware,
+    /// The watchdog is started by software
+    Software,
+}
+
+/// Confirmation of read protection level 2, which can't be undone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level2 {
+    /// Refuse to program read protection level 2
+    Refuse,
+    /// Program read protection level 2
+    Confirm,
 }
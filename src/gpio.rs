@@ -1,13 +1,15 @@
 //! General Purpose Input / Output
 
-// TODO the pins here currently correspond to the LQFP-100 package. There
-// should be Cargo features that let you select different microcontroller
-// packages
+// NOTE the available pins depend on the package selected with one of the
+// `lqfp48`, `lqfp64`, `lqfp100` or `lqfp144` Cargo features; LQFP-100 is
+// assumed when no package is selected
 
-use crate::rcc::AHB;
+use crate::pac::gpiob;
+use crate::rcc::{Enable, Reset, AHB};
 use bobbin_bits::*;
 use core::intrinsics::transmute;
 use core::marker::PhantomData;
+use cortex_m::interrupt;
 #[allow(deprecated)]
 use hal::digital::{toggleable, v2, InputPin, OutputPin, StatefulOutputPin};
 
@@ -19,6 +21,32 @@ pub trait GPIOPin {
     fn index(&self) -> u8;
 }
 
+/// Pin that is statically known to belong to a port
+pub trait PortPin: GPIOPin {
+    /// GPIO peripheral the pin belongs to
+    type Port;
+    /// Current pin mode
+    type Mode: PinMode;
+}
+
+/// Pin that can be switched into alternate function mode
+///
+/// Lets drivers configure any pin they are handed
+pub trait AltFnPin<AFN: AltFnNum, OT: OutputType, OS: OutputSpeed> {
+    /// The pin in alternate function mode
+    type Output: GPIOPin;
+    /// Set io_mode to altfn with the given function, output type and speed
+    fn into_alt_fn(self, af: AFN, ot: OT, os: OS) -> Self::Output;
+}
+
+/// Alternate function pin that can be switched to open-drain output
+pub trait AltFnOpenDrain<PT: PullType> {
+    /// The pin in open-drain mode with pull `PT`
+    type Output: GPIOPin;
+    /// Sets output type to open-drain and pull to `pt`
+    fn into_open_drain(self, pt: PT) -> Self::Output;
+}
+
 /// Trait for pin mode
 pub trait PinMode {
     /// Convert type state into actual bits
@@ -45,12 +73,27 @@ impl<OT: OutputType, OS: OutputSpeed> PinMode for Output<OT, OS> {
     }
 }
 
+/// Marker trait for output modes
+pub trait OutputMode: PinMode {}
+impl<OT: OutputType, OS: OutputSpeed> OutputMode for Output<OT, OS> {}
+
+/// Marker trait for alternate function modes
+pub trait AltFnMode: PinMode {
+    /// Selected alternate function
+    type AF: AltFnNum;
+}
+
 /// Alternating function
 pub struct AltFn<AN: AltFnNum, OT: OutputType, OS: OutputSpeed> {
     _afnum: PhantomData<AN>,
     _output_mode: PhantomData<OT>,
     _output_speed: PhantomData<OS>,
 }
+impl<AN: AltFnNum, OT: OutputType, OS: OutputSpeed> AltFnMode
+    for AltFn<AN, OT, OS>
+{
+    type AF = AN;
+}
 impl<AN: AltFnNum, OT: OutputType, OS: OutputSpeed> PinMode
     for AltFn<AN, OT, OS>
 {
@@ -74,6 +117,7 @@ pub trait PullType {
 }
 
 /// No pull; floating
+#[derive(Clone, Copy)]
 pub struct PullNone;
 impl PullType for PullNone {
     fn pull_type(&self) -> U2 {
@@ -82,6 +126,7 @@ impl PullType for PullNone {
 }
 
 /// Pull up
+#[derive(Clone, Copy)]
 pub struct PullUp;
 impl PullType for PullUp {
     fn pull_type(&self) -> U2 {
@@ -90,6 +135,7 @@ impl PullType for PullUp {
 }
 
 /// Pull down
+#[derive(Clone, Copy)]
 pub struct PullDown;
 impl PullType for PullDown {
     fn pull_type(&self) -> U2 {
@@ -98,6 +144,7 @@ impl PullType for PullDown {
 }
 
 /// Reserved
+#[derive(Clone, Copy)]
 pub struct PullReserved;
 impl PullType for PullReserved {
     fn pull_type(&self) -> U2 {
@@ -175,6 +222,7 @@ macro_rules! gen_af {
     ([$(($af:ident, $bit:ident)) , +]) => {
         $(
             /// $af
+            #[derive(Default)]
             pub struct $af;
             impl AltFnNum for $af {
                 fn alt_fn_num(&self) -> U4 {
@@ -212,14 +260,15 @@ pub trait GpioExt {
 }
 
 macro_rules! gpio {
-    ($GPIOX:ident, $Gpiox:ident, $gpiox:ident, $iopxenr:ident, $iopxrst:ident, $group: ident, $PXx:ident, [
-        $($PXi:ident: ($pxi:ident, $i:expr, $AFR:ident),)+
+    ($GPIOX:ident, $Gpiox:ident, $gpiox:ident, $group: ident, $PXx:ident, [
+        $($(#[$attr:meta])* $PXi:ident: ($pxi:ident, $i:expr, $AFR:ident),)+
     ]) => {
         use crate::pac::$GPIOX;
         /// GPIO ports
         pub struct $Gpiox {
             $(
                 /// Pin $PXi
+                $(#[$attr])*
                 pub $pxi: $PXi<PullNone, Input>,
             )+
         }
@@ -228,12 +277,12 @@ macro_rules! gpio {
             type Ports = $Gpiox;
 
             fn split(self, ahb: &mut AHB) -> Self::Ports {
-                ahb.enr().modify(|_, w| w.$iopxenr().enabled());
-                ahb.rstr().modify(|_, w| w.$iopxrst().set_bit());
-                ahb.rstr().modify(|_, w| w.$iopxrst().clear_bit());
+                $GPIOX::enable(ahb);
+                $GPIOX::reset(ahb);
 
                 $Gpiox {
                     $(
+                        $(#[$attr])*
                         $pxi: $PXi {
                             _pullup_state: PhantomData,
                             _pin_mode: PhantomData
@@ -273,7 +322,7 @@ macro_rules! gpio {
                 let otyper = unsafe { &(*$GPIOX::ptr()).otyper };
                 let type_bits:u32 = ot.output_type().into();
                 otyper.modify(|r, w| unsafe {
-                    w.bits(r.bits() | (type_bits << index))
+                    w.bits((r.bits() & !(0b1 << index)) | (type_bits << index))
                 });
             }
 
@@ -287,6 +336,37 @@ macro_rules! gpio {
             _pin_mode: PhantomData<PM>
         }
 
+        impl<PT: PullType, PM: PinMode> GPIOPin for $PXx<PT, PM> {
+            fn group(&self) -> Group {
+                Group::$group
+            }
+
+            fn index(&self) -> u8 {
+                self.i
+            }
+        }
+
+        impl<PT: PullType, PM: PinMode> PortPin for $PXx<PT, PM> {
+            type Port = $GPIOX;
+            type Mode = PM;
+        }
+
+        impl<PT: PullType, PM: PinMode> $PXx<PT, PM> {
+            /// Erases the port from the type
+            ///
+            /// This is useful when you want to collect pins of different
+            /// ports into an array where you need all the elements to have
+            /// the same type
+            pub fn downgrade(self) -> Pin<PT, PM> {
+                Pin {
+                    group: Group::$group,
+                    i: self.i,
+                    _pullup_state: PhantomData,
+                    _pin_mode: PhantomData
+                }
+            }
+        }
+
         #[allow(deprecated)]
         impl<PT: PullType, OT: OutputType, OS: OutputSpeed>
             OutputPin for $PXx<PT, Output<OT, OS>> {
@@ -330,12 +410,14 @@ macro_rules! gpio {
         }
 
         $(
+            $(#[$attr])*
             /// Pin
             pub struct $PXi<PT: PullType, PM: PinMode> {
                 _pullup_state: PhantomData<PT>,
                 _pin_mode: PhantomData<PM>
             }
 
+            $(#[$attr])*
             impl <PT: PullType, PM: PinMode> GPIOPin for $PXi<PT, PM> {
                 fn group(&self) -> Group {
                     Group::$group
@@ -346,6 +428,39 @@ macro_rules! gpio {
                 }
             }
 
+            $(#[$attr])*
+            impl<PT: PullType, PM: PinMode> PortPin for $PXi<PT, PM> {
+                type Port = $GPIOX;
+                type Mode = PM;
+            }
+
+            $(#[$attr])*
+            impl<PT: PullType,
+                 PM: PinMode,
+                 AFN: AltFnNum,
+                 OT: OutputType,
+                 OS: OutputSpeed> AltFnPin<AFN, OT, OS> for $PXi<PT, PM> {
+                type Output = $PXi<PT, AltFn<AFN, OT, OS>>;
+
+                fn into_alt_fn(self, af: AFN, ot: OT, os: OS) -> Self::Output {
+                    self.alternating(af).output_type(ot).output_speed(os)
+                }
+            }
+
+            $(#[$attr])*
+            impl<PT: PullType,
+                 NPT: PullType,
+                 AFN: AltFnNum,
+                 OT: OutputType,
+                 OS: OutputSpeed> AltFnOpenDrain<NPT> for $PXi<PT, AltFn<AFN, OT, OS>> {
+                type Output = $PXi<NPT, AltFn<AFN, OpenDrain, OS>>;
+
+                fn into_open_drain(self, pt: NPT) -> Self::Output {
+                    self.output_type(OpenDrain).pull_type(pt)
+                }
+            }
+
+            $(#[$attr])*
             impl<PT: PullType, PM: PinMode> $PXi<PT, PM> {
                 /// Erases the pin number from the type
                 ///
@@ -359,6 +474,39 @@ macro_rules! gpio {
                     }
                 }
 
+                /// Erases both the port and the pin number from the type
+                ///
+                /// See [`Pin`](struct.Pin.html)
+                pub fn erase(self) -> Pin<PT, PM> {
+                    self.downgrade().downgrade()
+                }
+
+                /// Converts the pin into a [`Flex`](struct.Flex.html) pin
+                /// whose mode can be changed at runtime
+                ///
+                /// The pin starts as an input.
+                pub fn into_flex(self) -> Flex<PT> {
+                    $Gpiox::set_pin_mode::<Input>($i);
+                    Flex { group: Group::$group,
+                           i: $i,
+                           mode: FlexMode::Input,
+                           _pullup_state: PhantomData }
+                }
+
+                /// Locks the pin configuration until the next reset
+                ///
+                /// Only the first lock sequence applied to a port takes
+                /// effect, use [`Port::lock`](struct.Port.html#method.lock)
+                /// to lock several pins of the same port. The pin is given
+                /// back if the lock didn't take effect.
+                pub fn lock(self) -> Result<Locked<Self>, Self> {
+                    if lock(Group::$group, 1 << $i) {
+                        Ok(Locked { pin: self })
+                    } else {
+                        Err(self)
+                    }
+                }
+
                 /// Sets pull type: Floaing, PullUp, PullDown
                 pub fn pull_type<NPT: PullType>(self, pt: NPT)
                                                 -> $PXi<NPT, PM>
@@ -415,6 +563,7 @@ macro_rules! gpio {
                 }
             }
 
+            $(#[$attr])*
             impl<PT: PullType, OT: OutputType, OS: OutputSpeed> $PXi<PT, Output<OT, OS>> {
                 /// Set output type
                 pub fn output_type<NOT: OutputType>(self, ot: NOT) -> $PXi<PT, Output<NOT, OS>> {
@@ -439,6 +588,7 @@ macro_rules! gpio {
                 }
             }
 
+            $(#[$attr])*
             impl<PT: PullType, AFN: AltFnNum, OT: OutputType, OS: OutputSpeed> $PXi<PT, AltFn<AFN, OT, OS>> {
                 /// Set output type
                 pub fn output_type<NOT: OutputType>(self, ot: NOT) -> $PXi<PT, AltFn<AFN, NOT, OS>> {
@@ -467,6 +617,7 @@ macro_rules! gpio {
                 }
             }
 
+            $(#[$attr])*
             #[allow(deprecated)]
             impl<PT: PullType, OT:OutputType, OS:OutputSpeed> OutputPin
                 for $PXi<PT, Output<OT, OS>> {
@@ -481,6 +632,7 @@ macro_rules! gpio {
                     }
                 }
 
+            $(#[$attr])*
             #[allow(deprecated)]
             impl<PT: PullType, AN: AltFnNum, OT:OutputType, OS:OutputSpeed> OutputPin
                 for $PXi<PT, AltFn<AN, OT, OS>> {
@@ -495,6 +647,7 @@ macro_rules! gpio {
                     }
                 }
 
+            $(#[$attr])*
             #[allow(deprecated)]
             impl<PT: PullType, OT:OutputType, OS:OutputSpeed> StatefulOutputPin
                 for $PXi<PT, Output<OT, OS>> {
@@ -508,13 +661,16 @@ macro_rules! gpio {
                     }
                 }
 
+            $(#[$attr])*
             #[allow(deprecated)]
             impl<PT: PullType, OT:OutputType, OS:OutputSpeed> toggleable::Default
                 for $PXi<PT, Output<OT, OS>> {}
 
+            $(#[$attr])*
             impl<PT: PullType, OT:OutputType, OS:OutputSpeed> v2::toggleable::Default
                 for $PXi<PT, Output<OT, OS>> {}
 
+            $(#[$attr])*
             #[allow(deprecated)]
             impl<PT: PullType> InputPin for $PXi<PT, Input> {
                 fn is_high(&self) -> bool {
@@ -532,7 +688,8 @@ macro_rules! gpio {
     }
 }
 
-/// GPIO pin group [A-F]
+/// GPIO pin group [A-H]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Group {
     /// GPIOA
     A,
@@ -548,9 +705,534 @@ pub enum Group {
     F,
     /// GPIOG
     G,
+    /// GPIOH
+    H,
+}
+
+impl Group {
+    // NOTE all the ports share the register layout of GPIOB
+    fn ptr(&self) -> *const gpiob::RegisterBlock {
+        match self {
+            Group::A => crate::pac::GPIOA::ptr() as *const _,
+            Group::B => crate::pac::GPIOB::ptr(),
+            Group::C => crate::pac::GPIOC::ptr(),
+            Group::D => crate::pac::GPIOD::ptr(),
+            Group::E => crate::pac::GPIOE::ptr(),
+            Group::F => crate::pac::GPIOF::ptr(),
+            Group::G => crate::pac::GPIOG::ptr(),
+            Group::H => crate::pac::GPIOH::ptr(),
+        }
+    }
+}
+
+/// Fully erased pin
+///
+/// Both the port and the pin number are stored at runtime, so pins of
+/// different ports can be collected into a single array.
+pub struct Pin<PT: PullType, PM: PinMode> {
+    group: Group,
+    i: u8,
+    _pullup_state: PhantomData<PT>,
+    _pin_mode: PhantomData<PM>,
+}
+
+impl<PT: PullType, PM: PinMode> GPIOPin for Pin<PT, PM> {
+    fn group(&self) -> Group {
+        self.group
+    }
+
+    fn index(&self) -> u8 {
+        self.i
+    }
+}
+
+#[allow(deprecated)]
+impl<PT: PullType, OT: OutputType, OS: OutputSpeed> OutputPin
+    for Pin<PT, Output<OT, OS>>
+{
+    fn set_high(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.group.ptr()).bsrr.write(|w| w.bits(1 << self.i)) }
+    }
+
+    fn set_low(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe {
+            (*self.group.ptr()).bsrr
+                               .write(|w| w.bits(1 << (16 + self.i)))
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<PT: PullType, AN: AltFnNum, OT: OutputType, OS: OutputSpeed> OutputPin
+    for Pin<PT, AltFn<AN, OT, OS>>
+{
+    fn set_high(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.group.ptr()).bsrr.write(|w| w.bits(1 << self.i)) }
+    }
+
+    fn set_low(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe {
+            (*self.group.ptr()).bsrr
+                               .write(|w| w.bits(1 << (16 + self.i)))
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<PT: PullType, OT: OutputType, OS: OutputSpeed> StatefulOutputPin
+    for Pin<PT, Output<OT, OS>>
+{
+    fn is_set_high(&self) -> bool {
+        !self.is_set_low()
+    }
+
+    fn is_set_low(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*self.group.ptr()).odr.read().bits() & (1 << self.i) == 0 }
+    }
+}
+
+#[allow(deprecated)]
+impl<PT: PullType, OT: OutputType, OS: OutputSpeed> toggleable::Default
+    for Pin<PT, Output<OT, OS>>
+{
+}
+
+impl<PT: PullType, OT: OutputType, OS: OutputSpeed> v2::toggleable::Default
+    for Pin<PT, Output<OT, OS>>
+{
+}
+
+#[allow(deprecated)]
+impl<PT: PullType> InputPin for Pin<PT, Input> {
+    fn is_high(&self) -> bool {
+        !self.is_low()
+    }
+
+    fn is_low(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*self.group.ptr()).idr.read().bits() & (1 << self.i) == 0 }
+    }
+}
+
+/// Set of owned pins that belong to a single port
+pub trait PortPins {
+    /// GPIO peripheral the pins belong to
+    type Port;
+    /// The same pins with locked configuration
+    type Locked: PortPins<Port = Self::Port>;
+
+    /// Returns GPIO group
+    fn group(&self) -> Group;
+    /// Returns the mask of the pins within the port
+    fn mask(&self) -> u16;
+    #[doc(hidden)]
+    fn into_locked(self) -> Self::Locked;
+}
+
+/// Set of pins of a single port that are all outputs
+pub trait OutputPins: PortPins {}
+
+/// Set of pins of a single port that are all inputs
+pub trait InputPins: PortPins {}
+
+macro_rules! port_pins {
+    ($(($($P:ident: $i:tt),+),)+) => {
+        $(
+            impl<GPIO, $($P),+> PortPins for ($($P,)+)
+                where $($P: PortPin<Port = GPIO>),+
+            {
+                type Port = GPIO;
+                type Locked = ($(Locked<$P>,)+);
+
+                fn group(&self) -> Group {
+                    self.0.group()
+                }
+
+                fn mask(&self) -> u16 {
+                    0 $(| (1 << self.$i.index()))+
+                }
+
+                fn into_locked(self) -> Self::Locked {
+                    ($(Locked { pin: self.$i },)+)
+                }
+            }
+
+            impl<GPIO, $($P),+> OutputPins for ($($P,)+)
+                where $($P: PortPin<Port = GPIO>,
+                        <$P as PortPin>::Mode: OutputMode),+
+            {}
+
+            impl<GPIO, $($P),+> InputPins for ($($P,)+)
+                where $($P: PortPin<Port = GPIO, Mode = Input>),+
+            {}
+        )+
+    }
+}
+
+port_pins!(
+             (P0: 0),
+             (P0: 0, P1: 1),
+             (P0: 0, P1: 1, P2: 2),
+             (P0: 0, P1: 1, P2: 2, P3: 3),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10, P11: 11),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10, P11: 11, P12: 12),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10, P11: 11, P12: 12, P13: 13),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10, P11: 11, P12: 12, P13: 13, P14: 14),
+             (P0: 0, P1: 1, P2: 2, P3: 3, P4: 4, P5: 5, P6: 6, P7: 7, P8: 8, P9: 9, P10: 10, P11: 11, P12: 12, P13: 13, P14: 14, P15: 15),
+);
+
+/// Pins of a single port that are read or written at once
+///
+/// Every access goes through a single IDR read or BSRR write, so all the pins
+/// change state simultaneously. Bit `n` of masks and values corresponds to
+/// pin `n` of the port; bits of pins that are not owned are ignored.
+pub struct Port<PINS: PortPins> {
+    pins: PINS,
+    group: Group,
+    mask: u16,
+}
+
+impl<PINS: PortPins> Port<PINS> {
+    /// Groups pins of the same port
+    ///
+    /// Pins are passed as a tuple, e.g. `(pa0, pa1, pa5)`
+    pub fn new(pins: PINS) -> Self {
+        let group = pins.group();
+        let mask = pins.mask();
+        Port { pins, group, mask }
+    }
+
+    /// Returns the mask of the owned pins
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    /// Releases the pins
+    pub fn free(self) -> PINS {
+        self.pins
+    }
+
+    /// Locks the configuration of all the owned pins until the next reset
+    ///
+    /// Only the first lock sequence applied to a port takes effect, the
+    /// pins are given back if they were not locked by it.
+    ///
+    /// ```no_run
+    /// use alt_stm32f30x_hal::gpio::Port;
+    /// use alt_stm32f30x_hal::pac;
+    /// use alt_stm32f30x_hal::prelude::*;
+    ///
+    /// let dp = pac::Peripherals::take().unwrap();
+    /// let mut rcc = dp.RCC.constrain();
+    /// let gpioa = dp.GPIOA.split(&mut rcc.ahb);
+    ///
+    /// let locked = Port::new((gpioa.pa0, gpioa.pa1)).lock();
+    /// assert!(locked.is_ok());
+    /// // GPIOA is already locked, PA2 can't be locked anymore
+    /// let pa2 = match Port::new((gpioa.pa2,)).lock() {
+    ///     Ok(_) => unreachable!(),
+    ///     Err(port) => port.free().0,
+    /// };
+    /// ```
+    pub fn lock(self) -> Result<Port<PINS::Locked>, Port<PINS>> {
+        if lock(self.group, self.mask) {
+            Ok(Port { pins: self.pins.into_locked(),
+                      group: self.group,
+                      mask: self.mask })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<PINS: OutputPins> Port<PINS> {
+    /// Sets the pins selected by `mask` to the corresponding bits of `value`
+    pub fn write_masked(&mut self, mask: u16, value: u16) {
+        let mask = u32::from(mask & self.mask);
+        let value = u32::from(value);
+        let bits = (value & mask) | ((!value & mask) << 16);
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.group.ptr()).bsrr.write(|w| w.bits(bits)) }
+    }
+
+    /// Sets all the owned pins to the corresponding bits of `value`
+    pub fn write(&mut self, value: u16) {
+        self.write_masked(0xffff, value)
+    }
+}
+
+impl<PINS: InputPins> Port<PINS> {
+    /// Reads the state of the owned pins
+    pub fn read(&self) -> u16 {
+        // NOTE(unsafe) atomic read with no side effects
+        let idr = unsafe { (*self.group.ptr()).idr.read().bits() };
+        idr as u16 & self.mask
+    }
+}
+
+/// GPIO error
+#[derive(Debug)]
+pub enum Error {
+    /// Operation is not valid for the current pin mode
+    InvalidMode,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Mode of a [`Flex`](struct.Flex.html) pin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlexMode {
+    /// Input
+    Input,
+    /// Push pull output
+    PushPullOutput,
+    /// Open drain output
+    OpenDrainOutput,
+}
+
+/// Pin whose mode can be changed at runtime
+///
+/// Useful for bidirectional protocols (e.g. 1-Wire) where moving the pin
+/// between type states on every direction change is impractical. Operations
+/// that are not valid for the current mode return
+/// [`Error::InvalidMode`](enum.Error.html#variant.InvalidMode).
+pub struct Flex<PT: PullType> {
+    group: Group,
+    i: u8,
+    mode: FlexMode,
+    _pullup_state: PhantomData<PT>,
 }
 
-gpio!(GPIOA, Gpioa, gpioa, iopaen, ioparst, A, PAx, [
+impl<PT: PullType> Flex<PT> {
+    /// Returns current pin mode
+    pub fn mode(&self) -> FlexMode {
+        self.mode
+    }
+
+    /// Sets pin mode
+    pub fn set_mode(&mut self, mode: FlexMode) {
+        let gpio = unsafe { &*self.group.ptr() };
+        let index = u32::from(self.i);
+        let (mode_bits, type_bits): (u32, u32) = match mode {
+            FlexMode::Input => (0b00, 0),
+            FlexMode::PushPullOutput => (0b01, 0),
+            FlexMode::OpenDrainOutput => (0b01, 1),
+        };
+        gpio.otyper.modify(|r, w| unsafe {
+                       w.bits((r.bits() & !(1 << index))
+                              | (type_bits << index))
+                   });
+        let offset = 2 * index;
+        gpio.moder.modify(|r, w| unsafe {
+                      w.bits((r.bits() & !(0b11 << offset))
+                             | (mode_bits << offset))
+                  });
+        self.mode = mode;
+    }
+
+    /// Switches the pin to input
+    pub fn make_input(&mut self) {
+        self.set_mode(FlexMode::Input)
+    }
+
+    /// Switches the pin to push pull output
+    pub fn make_push_pull_output(&mut self) {
+        self.set_mode(FlexMode::PushPullOutput)
+    }
+
+    /// Switches the pin to open drain output
+    pub fn make_open_drain_output(&mut self) {
+        self.set_mode(FlexMode::OpenDrainOutput)
+    }
+
+    /// Switches the pin to input and erases it
+    pub fn free(mut self) -> Pin<PT, Input> {
+        self.make_input();
+        Pin { group: self.group,
+              i: self.i,
+              _pullup_state: PhantomData,
+              _pin_mode: PhantomData }
+    }
+}
+
+impl<PT: PullType> GPIOPin for Flex<PT> {
+    fn group(&self) -> Group {
+        self.group
+    }
+
+    fn index(&self) -> u8 {
+        self.i
+    }
+}
+
+impl<PT: PullType> v2::OutputPin for Flex<PT> {
+    type Error = Error;
+
+    fn set_high(&mut self) -> Result<(), Error> {
+        if self.mode == FlexMode::Input {
+            return Err(Error::InvalidMode);
+        }
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.group.ptr()).bsrr.write(|w| w.bits(1 << self.i)) }
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        if self.mode == FlexMode::Input {
+            return Err(Error::InvalidMode);
+        }
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe {
+            (*self.group.ptr()).bsrr
+                               .write(|w| w.bits(1 << (16 + self.i)))
+        }
+        Ok(())
+    }
+}
+
+impl<PT: PullType> v2::StatefulOutputPin for Flex<PT> {
+    fn is_set_high(&self) -> Result<bool, Error> {
+        self.is_set_low().map(|low| !low)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Error> {
+        if self.mode == FlexMode::Input {
+            return Err(Error::InvalidMode);
+        }
+        // NOTE(unsafe) atomic read with no side effects
+        Ok(unsafe {
+            (*self.group.ptr()).odr.read().bits() & (1 << self.i) == 0
+        })
+    }
+}
+
+impl<PT: PullType> v2::toggleable::Default for Flex<PT> {}
+
+impl<PT: PullType> v2::InputPin for Flex<PT> {
+    type Error = Error;
+
+    fn is_high(&self) -> Result<bool, Error> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&self) -> Result<bool, Error> {
+        // a push pull output drives the line, so reading it back is
+        // meaningless
+        if self.mode == FlexMode::PushPullOutput {
+            return Err(Error::InvalidMode);
+        }
+        // NOTE(unsafe) atomic read with no side effects
+        Ok(unsafe {
+            (*self.group.ptr()).idr.read().bits() & (1 << self.i) == 0
+        })
+    }
+}
+
+/// Pin with locked configuration
+///
+/// The pin keeps working as an input or output, but its mode, pull, output
+/// type, speed and alternate function can no longer be changed.
+pub struct Locked<P> {
+    pin: P,
+}
+
+impl<P: GPIOPin> Locked<P> {
+    /// Returns `true` if the lock took effect in hardware
+    pub fn is_locked(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        let lckr = unsafe { (*self.pin.group().ptr()).lckr.read().bits() };
+        lckr & LCKK != 0 && lckr & (1 << self.pin.index()) != 0
+    }
+}
+
+impl<P: GPIOPin> GPIOPin for Locked<P> {
+    fn group(&self) -> Group {
+        self.pin.group()
+    }
+
+    fn index(&self) -> u8 {
+        self.pin.index()
+    }
+}
+
+impl<P: PortPin> PortPin for Locked<P> {
+    type Port = P::Port;
+    type Mode = P::Mode;
+}
+
+#[allow(deprecated)]
+impl<P: OutputPin> OutputPin for Locked<P> {
+    fn set_high(&mut self) {
+        self.pin.set_high()
+    }
+
+    fn set_low(&mut self) {
+        self.pin.set_low()
+    }
+}
+
+#[allow(deprecated)]
+impl<P: StatefulOutputPin> StatefulOutputPin for Locked<P> {
+    fn is_set_high(&self) -> bool {
+        self.pin.is_set_high()
+    }
+
+    fn is_set_low(&self) -> bool {
+        self.pin.is_set_low()
+    }
+}
+
+#[allow(deprecated)]
+impl<P: OutputPin + StatefulOutputPin> toggleable::Default for Locked<P> {}
+
+#[allow(deprecated)]
+impl<P: OutputPin + StatefulOutputPin> v2::toggleable::Default for Locked<P> {}
+
+#[allow(deprecated)]
+impl<P: InputPin> InputPin for Locked<P> {
+    fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+}
+
+const LCKK: u32 = 1 << 16;
+
+/// Applies the LCKR key sequence to the pins in `mask`
+fn lock(group: Group, mask: u16) -> bool {
+    let lckr = unsafe { &(*group.ptr()).lckr };
+    let mask = u32::from(mask);
+    interrupt::free(|_| {
+        // NOTE(unsafe) the key sequence must not be interrupted:
+        // write 1, write 0, write 1, read
+        unsafe {
+            lckr.write(|w| w.bits(LCKK | mask));
+            lckr.write(|w| w.bits(mask));
+            lckr.write(|w| w.bits(LCKK | mask));
+        }
+        lckr.read();
+    });
+
+    let lckr = lckr.read().bits();
+    lckr & LCKK != 0 && lckr & mask == mask
+}
+
+gpio!(GPIOA, Gpioa, gpioa, A, PAx, [
     PA0: (pa0, 0, afrl),
     PA1: (pa1, 1, afrl),
     PA2: (pa2, 2, afrl),
@@ -569,7 +1251,7 @@ gpio!(GPIOA, Gpioa, gpioa, iopaen, ioparst, A, PAx, [
     PA15: (pa15, 15, afrh),
 ]);
 
-gpio!(GPIOB, Gpiob, gpiob, iopben, iopbrst, B, PBx, [
+gpio!(GPIOB, Gpiob, gpiob, B, PBx, [
     PB0: (pb0, 0, afrl),
     PB1: (pb1, 1, afrl),
     PB2: (pb2, 2, afrl),
@@ -588,45 +1270,75 @@ gpio!(GPIOB, Gpiob, gpiob, iopben, iopbrst, B, PBx, [
     PB15: (pb15, 15, afrh),
 ]);
 
-gpio!(GPIOC, Gpioc, gpioc, iopcen, iopcrst, C, PCx, [
+gpio!(GPIOC, Gpioc, gpioc, C, PCx, [
+    #[cfg(not(feature = "lqfp48"))]
     PC0: (pc0, 0, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC1: (pc1, 1, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC2: (pc2, 2, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC3: (pc3, 3, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC4: (pc4, 4, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC5: (pc5, 5, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC6: (pc6, 6, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC7: (pc7, 7, afrl),
+    #[cfg(not(feature = "lqfp48"))]
     PC8: (pc8, 8, afrh),
+    #[cfg(not(feature = "lqfp48"))]
     PC9: (pc9, 9, afrh),
+    #[cfg(not(feature = "lqfp48"))]
     PC10: (pc10, 10, afrh),
+    #[cfg(not(feature = "lqfp48"))]
     PC11: (pc11, 11, afrh),
+    #[cfg(not(feature = "lqfp48"))]
     PC12: (pc12, 12, afrh),
     PC13: (pc13, 13, afrh),
     PC14: (pc14, 14, afrh),
     PC15: (pc15, 15, afrh),
 ]);
 
-gpio!(GPIOD, Gpiod, gpiod, iopden, iopdrst, D, PDx, [
+#[cfg(not(feature = "lqfp48"))]
+gpio!(GPIOD, Gpiod, gpiod, D, PDx, [
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD0: (pd0, 0, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD1: (pd1, 1, afrl),
     PD2: (pd2, 2, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD3: (pd3, 3, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD4: (pd4, 4, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD5: (pd5, 5, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD6: (pd6, 6, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD7: (pd7, 7, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD8: (pd8, 8, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD9: (pd9, 9, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD10: (pd10, 10, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD11: (pd11, 11, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD12: (pd12, 12, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD13: (pd13, 13, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD14: (pd14, 14, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PD15: (pd15, 15, afrh),
 ]);
 
-gpio!(GPIOE, Gpioe, gpioe, iopeen, ioperst, E, PEx, [
+#[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
+gpio!(GPIOE, Gpioe, gpioe, E, PEx, [
     PE0: (pe0, 0, afrl),
     PE1: (pe1, 1, afrl),
     PE2: (pe2, 2, afrl),
@@ -645,12 +1357,62 @@ gpio!(GPIOE, Gpioe, gpioe, iopeen, ioperst, E, PEx, [
     PE15: (pe15, 15, afrh),
 ]);
 
-gpio!(GPIOF, Gpiof, gpiof, iopfen, iopfrst, F, PFx, [
+gpio!(GPIOF, Gpiof, gpiof, F, PFx, [
     PF0: (pf0, 0, afrl),
     PF1: (pf1, 1, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PF2: (pf2, 2, afrl),
-    PF4: (pf3, 4, afrl),
+    #[cfg(feature = "lqfp144")]
+    PF3: (pf3, 3, afrl),
+    #[cfg(not(feature = "lqfp48"))]
+    PF4: (pf4, 4, afrl),
+    #[cfg(feature = "lqfp144")]
+    PF5: (pf5, 5, afrl),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PF6: (pf6, 6, afrl),
+    #[cfg(feature = "lqfp144")]
+    PF7: (pf7, 7, afrl),
+    #[cfg(feature = "lqfp144")]
+    PF8: (pf8, 8, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PF9: (pf9, 9, afrh),
+    #[cfg(not(any(feature = "lqfp48", feature = "lqfp64")))]
     PF10: (pf10, 10, afrh),
+    #[cfg(feature = "lqfp144")]
+    PF11: (pf11, 11, afrh),
+    #[cfg(feature = "lqfp144")]
+    PF12: (pf12, 12, afrh),
+    #[cfg(feature = "lqfp144")]
+    PF13: (pf13, 13, afrh),
+    #[cfg(feature = "lqfp144")]
+    PF14: (pf14, 14, afrh),
+    #[cfg(feature = "lqfp144")]
+    PF15: (pf15, 15, afrh),
+]);
+
+#[cfg(feature = "lqfp144")]
+gpio!(GPIOG, Gpiog, gpiog, G, PGx, [
+    PG0: (pg0, 0, afrl),
+    PG1: (pg1, 1, afrl),
+    PG2: (pg2, 2, afrl),
+    PG3: (pg3, 3, afrl),
+    PG4: (pg4, 4, afrl),
+    PG5: (pg5, 5, afrl),
+    PG6: (pg6, 6, afrl),
+    PG7: (pg7, 7, afrl),
+    PG8: (pg8, 8, afrh),
+    PG9: (pg9, 9, afrh),
+    PG10: (pg10, 10, afrh),
+    PG11: (pg11, 11, afrh),
+    PG12: (pg12, 12, afrh),
+    PG13: (pg13, 13, afrh),
+    PG14: (pg14, 14, afrh),
+    PG15: (pg15, 15, afrh),
+]);
+
+#[cfg(feature = "lqfp144")]
+gpio!(GPIOH, Gpioh, gpioh, H, PHx, [
+    PH0: (ph0, 0, afrl),
+    PH1: (ph1, 1, afrl),
+    PH2: (ph2, 2, afrl),
 ]);
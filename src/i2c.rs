@@ -1,14 +1,11 @@
 //! Inter-Integrated Circuit (I2C) bus
 
 use cast::u8;
-use crate::pac::{I2C1, I2C2, RCC};
-
-use crate::gpio::{AltFn, PinMode, PullType};
-use crate::gpio::{HighSpeed, PushPull, AF4};
-use crate::gpio::{PA10, PA14, PA15, PA9};
-use crate::gpio::{PB6, PB7, PB8, PB9};
-use crate::gpio::{PF0, PF1, PF6};
-use crate::rcc::Clocks;
+use crate::pac::{i2c1, I2C1, I2C2};
+
+use crate::gpio::{AltFnMode, AltFnNum, AltFnOpenDrain, PortPin};
+use crate::gpio::{PullNone, PullType, PullUp};
+use crate::rcc::{self, Clocks, Enable, RccBus, Reset};
 use crate::time::Hertz;
 use hal::blocking::i2c::{Read, Write, WriteRead};
 
@@ -33,21 +30,55 @@ pub enum Error {
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    freq: Hertz<u32>,
+}
+
+/// SCL pin of `I2C`
+///
+/// Implemented for every pin that can be routed to `I2C` on the selected
+/// device and package
+pub trait PinScl<I2C> {
+    /// Alternate function that connects the pin to `I2C`
+    type AF: AltFnNum + Default;
+}
+
+/// SDA pin of `I2C`
+pub trait PinSda<I2C> {
+    /// Alternate function that connects the pin to `I2C`
+    type AF: AltFnNum + Default;
 }
 
+/// Pull applied to the I2C lines
+///
+/// `PullNone` relies on external pull-up resistors, `PullUp` enables the
+/// internal ones, which are only strong enough for short, slow buses
+pub trait Pull: PullType + Copy {}
+impl Pull for PullNone {}
+impl Pull for PullUp {}
+
 /// I2c extension for I2C
-pub trait I2cExt<I2C, ISCL, ISDA, SCL, SDA> {
+pub trait I2cExt<I2C: RccBus, SCL, SDA> {
     /// Configures the I2c peripheral to work in master mode
     /// Consumes I2c peripheral and pair of (SCL, SDA) pins.
+    /// Pins must already be switched to the matching alternate function;
+    /// they are reconfigured as open-drain with the given pull, their speed
+    /// is left as configured.
     /// Returns [`I2c`].
     ///
     /// [`I2c`]: ./struct.I2c.html
-    fn i2c<F>(self,
-              pins: (ISCL, ISDA),
-              freq: F,
-              clocks: Clocks)
-              -> I2c<I2C, (SCL, SDA)>
-        where F: Into<Hertz<u32>>;
+    fn i2c<PU, F>(self,
+                  pins: (SCL, SDA),
+                  pull: PU,
+                  freq: F,
+                  clocks: Clocks,
+                  apb: &mut I2C::Bus)
+                  -> I2c<I2C,
+                         (<SCL as AltFnOpenDrain<PU>>::Output,
+                          <SDA as AltFnOpenDrain<PU>>::Output)>
+        where PU: Pull,
+              SCL: AltFnOpenDrain<PU>,
+              SDA: AltFnOpenDrain<PU>,
+              F: Into<Hertz<u32>>;
 }
 
 macro_rules! busy_wait {
@@ -70,140 +101,141 @@ macro_rules! busy_wait {
     };
 }
 
-macro_rules! i2c {
-    ($I2CX:ident,
-     $i2cXen:ident,
-     $i2cXrst:ident,
-     $afn:ident,
-     $speed:ident,
-     scl: [$($scl: ident, )+],
-     sda: $sda: tt
-    ) => {
-        i2c!{
-            $I2CX,
-            $i2cXen,
-            $i2cXrst,
-            $afn,
-            $speed,
-            [$(
-                ($scl, $sda),
-            )+]
-        }
+/// Writes the TIMINGR settings for a `freq` bus clock from `i2cclk`
+fn set_timing(i2c: &i2c1::RegisterBlock, i2cclk: u32, freq: u32) {
+    // TODO review compliance with the timing requirements of I2C
+    // t_I2CCLK = 1 / I2CCLK
+    // t_PRESC  = (PRESC + 1) * t_I2CCLK
+    // t_SCLL   = (SCLL + 1) * t_PRESC
+    // t_SCLH   = (SCLH + 1) * t_PRESC
+    //
+    // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
+    // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
+    let ratio = i2cclk / freq - 4;
+    let (presc, scll, sclh, sdadel, scldel) = if freq >= 100_000 {
+        // fast-mode or fast-mode plus
+        // here we pick SCLL + 1 = 2 * (SCLH + 1)
+        let presc = ratio / 387;
+        let sclh = ((ratio / (presc + 1)) - 3) / 3;
+        let scll = 2 * (sclh + 1) - 1;
+        let (sdadel, scldel) = if freq > 400_000 {
+            // fast-mode plus
+            let sdadel = 0;
+            let scldel = i2cclk / 4_000_000 / (presc + 1) - 1;
+            (sdadel, scldel)
+        } else {
+            // fast-mode
+            let sdadel = i2cclk / 8_000_000 / (presc + 1);
+            let scldel = i2cclk / 2_000_000 / (presc + 1) - 1;
+            (sdadel, scldel)
+        };
+        (presc, scll, sclh, sdadel, scldel)
+    } else {
+        // standard-mode
+        // here we pick SCLL = SCLH
+        let presc = ratio / 514;
+        let sclh = ((ratio / (presc + 1)) - 2) / 2;
+        let scll = sclh;
+        let sdadel = i2cclk / 2_000_000 / (presc + 1);
+        let scldel = i2cclk / 800_000 / (presc + 1) - 1;
+        (presc, scll, sclh, sdadel, scldel)
     };
-    ($I2CX:ident,
-     $i2cXen:ident,
-     $i2cXrst:ident,
-     $afn:ident,
-     $speed:ident,
-     [$(($scl: ident, [$($sda:ident, )+]),)+]
-    ) => {
-        $(
-            $(
-                impl<PT: PullType, PM: PinMode>
-                    I2cExt<$I2CX,
-                $scl<PT, PM>,
-                $sda<PT, PM>,
-                $scl<PT, AltFn<$afn, PushPull, $speed>>,
-                $sda<PT, AltFn<$afn, PushPull, $speed>>> for $I2CX
-                {
-                    fn i2c<F>(
-                        self,
-                        pins: ($scl<PT, PM>, $sda<PT, PM>),
-                        freq: F,
-                        clocks: Clocks)
-                        -> I2c<$I2CX,
-                    ($scl<PT, AltFn<$afn, PushPull, $speed>>,
-                     $sda<PT, AltFn<$afn, PushPull, $speed>>)> where
-                        F: Into<Hertz<u32>>,
-                    {
-                        let outpins = (pins.0.alternating($afn).output_speed($speed),
-                                       pins.1.alternating($afn).output_speed($speed));
-                        let apbenr = unsafe { &(*RCC::ptr()).apb1enr };
-                        let apbrstr = unsafe { &(*RCC::ptr()).apb1rstr };
-
-                        apbenr.modify(|_, w| w.$i2cXen().enabled());
-                        apbrstr.modify(|_, w| w.$i2cXrst().set_bit());
-                        apbrstr.modify(|_, w| w.$i2cXrst().clear_bit());
-
-                        let freq = freq.into().0;
-
-                        // TODO: remove assert, return error?
-                        assert!(freq <= 1_000_000);
-
-                        // TODO review compliance with the timing requirements of I2C
-                        // t_I2CCLK = 1 / PCLK1
-                        // t_PRESC  = (PRESC + 1) * t_I2CCLK
-                        // t_SCLL   = (SCLL + 1) * t_PRESC
-                        // t_SCLH   = (SCLH + 1) * t_PRESC
-                        //
-                        // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
-                        // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
-                        let i2cclk = clocks.pclk1().0;
-                        let ratio = i2cclk / freq - 4;
-                        let (presc, scll, sclh, sdadel, scldel) = if freq >= 100_000 {
-                            // fast-mode or fast-mode plus
-                            // here we pick SCLL + 1 = 2 * (SCLH + 1)
-                            let presc = ratio / 387;
-                            let sclh = ((ratio / (presc + 1)) - 3) / 3;
-                            let scll = 2 * (sclh + 1) - 1;
-                            let (sdadel, scldel) = if freq > 400_000 {
-                                // fast-mode plus
-                                let sdadel = 0;
-                                let scldel = i2cclk / 4_000_000 / (presc + 1) - 1;
-                                (sdadel, scldel)
-                            } else {
-                                // fast-mode
-                                let sdadel = i2cclk / 8_000_000 / (presc + 1);
-                                let scldel = i2cclk / 2_000_000 / (presc + 1) - 1;
-                                (sdadel, scldel)
-                            };
-                            (presc, scll, sclh, sdadel, scldel)
-                        } else {
-                            // standard-mode
-                            // here we pick SCLL = SCLH
-                            let presc = ratio / 514;
-                            let sclh = ((ratio / (presc + 1)) - 2) / 2;
-                            let scll = sclh;
-                            let sdadel = i2cclk / 2_000_000 / (presc + 1);
-                            let scldel = i2cclk / 800_000 / (presc + 1) - 1;
-                            (presc, scll, sclh, sdadel, scldel)
-                        };
-                        // TODO: remove asserts
-                        let presc = u8(presc).unwrap();
-                        assert!(presc < 16);
-                        let scldel = u8(scldel).unwrap();
-                        assert!(scldel < 16);
-                        let sdadel = u8(sdadel).unwrap();
-                        assert!(sdadel < 16);
-                        let sclh = u8(sclh).unwrap();
-                        let scll = u8(scll).unwrap();
-
-                        // Configure for "fast mode" (400 KHz)
-                        self.timingr.write(|w|
-                            w.presc()
-                                .bits(presc)
-                                .scll()
-                                .bits(scll)
-                                .sclh()
-                                .bits(sclh)
-                                .sdadel()
-                                .bits(sdadel)
-                                .scldel()
-                                .bits(scldel)
-                        );
-
-                        // Enable the peripheral
-                        self.cr1.write(|w| w.pe().set_bit());
-
-                        I2c { i2c: self, pins: outpins }
-                    }
+    // TODO: remove asserts
+    let presc = u8(presc).unwrap();
+    assert!(presc < 16);
+    let scldel = u8(scldel).unwrap();
+    assert!(scldel < 16);
+    let sdadel = u8(sdadel).unwrap();
+    assert!(sdadel < 16);
+    let sclh = u8(sclh).unwrap();
+    let scll = u8(scll).unwrap();
+
+    // Configure for "fast mode" (400 KHz)
+    i2c.timingr.write(|w| {
+                   w.presc()
+                    .bits(presc)
+                    .scll()
+                    .bits(scll)
+                    .sclh()
+                    .bits(sclh)
+                    .sdadel()
+                    .bits(sdadel)
+                    .scldel()
+                    .bits(scldel)
+               });
+}
+
+macro_rules! i2c {
+    ($I2CX:ident, $i2cXclk:ident, $i:expr) => {
+        impl<SCL, SDA> I2cExt<$I2CX, SCL, SDA> for $I2CX
+            where SCL: PinScl<$I2CX> + PortPin,
+                  SCL::Mode: AltFnMode<AF = <SCL as PinScl<$I2CX>>::AF>,
+                  SDA: PinSda<$I2CX> + PortPin,
+                  SDA::Mode: AltFnMode<AF = <SDA as PinSda<$I2CX>>::AF>
+        {
+            fn i2c<PU, F>(
+                self,
+                pins: (SCL, SDA),
+                pull: PU,
+                freq: F,
+                clocks: Clocks,
+                apb: &mut <$I2CX as RccBus>::Bus)
+                -> I2c<$I2CX,
+                       (<SCL as AltFnOpenDrain<PU>>::Output,
+                        <SDA as AltFnOpenDrain<PU>>::Output)> where
+                PU: Pull,
+                SCL: AltFnOpenDrain<PU>,
+                SDA: AltFnOpenDrain<PU>,
+                F: Into<Hertz<u32>>,
+            {
+                // the bus is wired-AND: never drive a line high
+                let pins = (pins.0.into_open_drain(pull),
+                            pins.1.into_open_drain(pull));
+
+                $I2CX::enable(apb);
+                $I2CX::reset(apb);
+
+                let freq = freq.into().0;
+
+                // TODO: remove assert, return error?
+                assert!(freq <= 1_000_000);
+
+                if clocks.i2c_hsi($i) {
+                    rcc::enable_hsi();
                 }
-            )+
-        )+
+                set_timing(&self, clocks.$i2cXclk().0, freq);
+
+                // Enable the peripheral
+                self.cr1.write(|w| w.pe().set_bit());
+
+                I2c { i2c: self, pins, freq: Hertz(freq) }
+            }
+        }
 
         impl<SCL, SDA> I2c<$I2CX, (SCL, SDA)> {
+            /// Recomputes the bus timing after the clock tree was
+            /// reconfigured
+            ///
+            /// Must not be called while a transfer is ongoing. Turns the HSI
+            /// back on if the I2C runs from it, as applying a clock tree that
+            /// uses the HSE turns it off.
+            pub fn reclock(&mut self, clocks: &Clocks) {
+                // TIMINGR can only be written while the I2C is disabled
+                self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                if clocks.i2c_hsi($i) {
+                    rcc::enable_hsi();
+                }
+                set_timing(&self.i2c, clocks.$i2cXclk().0, self.freq.0);
+                self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+            }
+
             /// Releases the I2C peripheral and associated pins
-            pub fn free(self) -> ($I2CX, (SCL, SDA)) {
+            ///
+            /// The I2C clock is gated off.
+            pub fn free(self,
+                        apb: &mut <$I2CX as RccBus>::Bus)
+                        -> ($I2CX, (SCL, SDA)) {
+                $I2CX::disable(apb);
                 (self.i2c, self.pins)
             }
         }
@@ -360,12 +392,8 @@ macro_rules! i2c {
     }
 }
 
-i2c!(I2C1, i2c1en, i2c1rst, AF4, HighSpeed,
-     scl: [PB6, PB8, PA15, ],
-     sda: [PB7, PB9, PA14, ]);
-i2c!(I2C2, i2c2en, i2c2rst, AF4, HighSpeed,
-     scl: [PA9, PF1, PF6, ],
-     sda: [PA10, PF0, ]);
+i2c!(I2C1, i2c1clk, 0);
+i2c!(I2C2, i2c2clk, 1);
 
 // hal! {
 //     I2C1: (i2c1, i2c1en, i2c1rst),
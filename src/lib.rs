@@ -16,6 +16,10 @@
 //! [cortex-m-quickstart] instructions and add this crate as a dependency in
 //! step number 5 and make sure you enable the "rt" Cargo feature of this crate.
 //!
+//! The available GPIO pins follow the LQFP-100 package by default; select a
+//! different package with one of the `lqfp48`, `lqfp64` or `lqfp144` Cargo
+//! features.
+//!
 //! [cortex-m-quickstart]: https://docs.rs/cortex-m-quickstart
 //!
 //! # Examples
@@ -47,18 +51,34 @@ pub use stm32f3::stm32f3x4 as pac;
 #[cfg(feature = "stm32f328")]
 pub use stm32f3::stm32f3x8 as pac;
 
+#[cfg(any(all(feature = "lqfp48",
+                any(feature = "lqfp64",
+                    feature = "lqfp100",
+                    feature = "lqfp144")),
+            all(feature = "lqfp64",
+                any(feature = "lqfp100", feature = "lqfp144")),
+            all(feature = "lqfp100", feature = "lqfp144")))]
+compile_error!("Only one package feature (lqfp48, lqfp64, lqfp100 or \
+                lqfp144) can be selected");
+
+#[cfg(all(feature = "lqfp144", not(feature = "xde")))]
+compile_error!("LQFP-144 parts are xD/xE parts, enable the xde feature");
+
 #[cfg(feature = "device-selected")]
 pub use crate::pac as device;
 
 #[cfg(feature = "device-selected")]
 pub use crate::pac as stm32;
 
+#[cfg(feature = "device-selected")]
+mod af;
 #[cfg(feature = "device-selected")]
 pub mod bb;
 #[cfg(feature = "device-selected")]
 pub mod delay;
 #[cfg(feature = "device-selected")]
 pub mod dma;
+pub mod eeprom;
 #[cfg(feature = "device-selected")]
 pub mod exti;
 #[cfg(feature = "device-selected")]
@@ -1,13 +1,23 @@
 //! Reset and Clock Control
 
-use core::cmp;
-
 use cast::u32;
-use crate::pac::{rcc, RCC};
+use core::cmp;
+use crate::pac::rcc::bdcr::LSEDRVW;
+#[cfg(feature = "xde")]
+use crate::pac::rcc::cfgr::MCOPREW;
+use crate::pac::rcc::cfgr::MCOW;
+use crate::pac::{rcc, PWR, RCC, TIM16};
 
 use crate::flash::ACR;
+use crate::gpio::{AltFn, OutputSpeed, OutputType, PullType, AF0, PA8};
 use crate::time::Hertz;
 
+use self::plan::{flash_latency, KernelClocks, HSI, LSE, LSI, PLL_OUT_MIN, USB};
+pub use self::plan::{ClockError, ClockPlan, HseBypass, HseDivider, I2cClock,
+                     SysclkSource, TimClock, UsartClock, CFGR};
+
+mod plan;
+
 /// Extension trait that constrains the `RCC` peripheral
 pub trait RccExt {
     /// Constrains the `RCC` peripheral so it plays nicely with the other
@@ -20,11 +30,11 @@ impl RccExt for RCC {
         Rcc { ahb: AHB { _0: () },
               apb1: APB1 { _0: () },
               apb2: APB2 { _0: () },
-              cfgr: CFGR { hse: None,
-                           hclk: None,
-                           pclk1: None,
-                           pclk2: None,
-                           sysclk: None } }
+              hsi: HSI { _0: () },
+              mco: MCO { _0: () },
+              bdcr: BDCR { _0: () },
+              csr: CSR { _0: () },
+              cfgr: CFGR::new() }
     }
 }
 
@@ -36,6 +46,14 @@ pub struct Rcc {
     pub apb1: APB1,
     /// Advanced Peripheral Bus 2 (APB2) registers
     pub apb2: APB2,
+    /// High speed internal oscillator trimming
+    pub hsi: HSI,
+    /// Microcontroller clock output
+    pub mco: MCO,
+    /// Backup domain control register
+    pub bdcr: BDCR,
+    /// Control/status register
+    pub csr: CSR,
     /// Clock configuration
     pub cfgr: CFGR,
 }
@@ -63,10 +81,6 @@ pub struct APB1 {
 }
 
 impl APB1 {
-    pub(crate) fn internal_get() -> Self {
-        APB1 { _0: () }
-    }
-
     pub(crate) fn enr(&mut self) -> &rcc::APB1ENR {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*RCC::ptr()).apb1enr }
@@ -88,341 +102,961 @@ impl APB2 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*RCC::ptr()).apb2enr }
     }
+
+    pub(crate) fn rstr(&mut self) -> &rcc::APB2RSTR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).apb2rstr }
+    }
 }
 
-/// HSE Configuration of clock, divider and bypass
-struct HseConfig {
-    /// Clock speed of HSE
-    speed: u32,
-    /// Divider to be used, output will be HSE / divider
-    divider: u32,
-    /// If the clock driving circuitry is bypassed i.e. using an oscillator,
-    /// not a crystal or resonator
-    bypass: bool,
+/// Bus a peripheral is connected to
+pub trait RccBus {
+    /// Proxy of the bus registers
+    type Bus;
 }
 
-/// HSE bypass selector
-pub enum HseBypass {
-    /// If the clock driving circuitry is bypassed i.e. using an oscillator
-    Enable,
-    /// If the clock driving circuitry is not bypassed i.e. using a crystal or
-    /// resonator
-    Disable,
+/// Clock gating of a peripheral
+pub trait Enable: RccBus {
+    /// Enables the peripheral clock
+    fn enable(bus: &mut Self::Bus);
+
+    /// Disables the peripheral clock
+    fn disable(bus: &mut Self::Bus);
 }
 
-/// HSE divide selector
-pub enum HseDivider {
-    /// Do not divide HSE clock
-    NoDivision,
-    /// Divide HSE clock by 2
-    DivideBy2,
-    /// Divide HSE clock by 3
-    DivideBy3,
-    /// Divide HSE clock by 4
-    DivideBy4,
-    /// Divide HSE clock by 5
-    DivideBy5,
-    /// Divide HSE clock by 6
-    DivideBy6,
-    /// Divide HSE clock by 7
-    DivideBy7,
-    /// Divide HSE clock by 8
-    DivideBy8,
-    /// Divide HSE clock by 9
-    DivideBy9,
-    /// Divide HSE clock by 10
-    DivideBy10,
-    /// Divide HSE clock by 11
-    DivideBy11,
-    /// Divide HSE clock by 12
-    DivideBy12,
-    /// Divide HSE clock by 13
-    DivideBy13,
-    /// Divide HSE clock by 14
-    DivideBy14,
-    /// Divide HSE clock by 15
-    DivideBy15,
-    /// Divide HSE clock by 16
-    DivideBy16,
-}
-
-const HSI: u32 = 8_000_000; // Hz
-
-/// Clock configuration
-pub struct CFGR {
-    hse: Option<HseConfig>,
-    hclk: Option<u32>,
-    pclk1: Option<u32>,
-    pclk2: Option<u32>,
-    sysclk: Option<u32>,
+/// Reset of a peripheral
+pub trait Reset: RccBus {
+    /// Resets the peripheral registers
+    fn reset(bus: &mut Self::Bus);
 }
 
-impl CFGR {
-    /// Sets a HseConfig that checks that the HSE divider is valid and if the
-    /// clock is in bypass mode
-    pub fn hse<F>(mut self,
-                  freq: F,
-                  divider: HseDivider,
-                  bypass: HseBypass)
-                  -> Self
-        where F: Into<Hertz<u32>>
-    {
-        self.hse = Some(HseConfig { speed: freq.into().0,
-                                    divider: match divider {
-                                        HseDivider::NoDivision => 1,
-                                        HseDivider::DivideBy2 => 2,
-                                        HseDivider::DivideBy3 => 3,
-                                        HseDivider::DivideBy4 => 4,
-                                        HseDivider::DivideBy5 => 5,
-                                        HseDivider::DivideBy6 => 6,
-                                        HseDivider::DivideBy7 => 7,
-                                        HseDivider::DivideBy8 => 8,
-                                        HseDivider::DivideBy9 => 9,
-                                        HseDivider::DivideBy10 => 10,
-                                        HseDivider::DivideBy11 => 11,
-                                        HseDivider::DivideBy12 => 12,
-                                        HseDivider::DivideBy13 => 13,
-                                        HseDivider::DivideBy14 => 14,
-                                        HseDivider::DivideBy15 => 15,
-                                        HseDivider::DivideBy16 => 16,
-                                    },
-                                    bypass: match bypass {
-                                        HseBypass::Disable => false,
-                                        HseBypass::Enable => true,
-                                    } });
-
-        self
-    }
-
-    /// Sets a frequency for the AHB bus
-    pub fn hclk<F>(mut self, freq: F) -> Self
-        where F: Into<Hertz<u32>>
-    {
-        self.hclk = Some(freq.into().0);
-        self
-    }
+/// Clock of the bus a peripheral is connected to
+pub trait BusClock {
+    /// Returns the frequency of the bus
+    fn clock(clocks: &Clocks) -> Hertz<u32>;
+}
 
-    /// Sets a frequency for the APB1 bus
-    pub fn pclk1<F>(mut self, freq: F) -> Self
-        where F: Into<Hertz<u32>>
-    {
-        self.pclk1 = Some(freq.into().0);
-        self
+macro_rules! bus {
+    ($($PER:ident: ($Bus:ident,
+                    $pclk:ident,
+                    $perXen:ident
+                    $(, $perXrst:ident)?),)+) => {
+        $(
+            impl RccBus for crate::pac::$PER {
+                type Bus = $Bus;
+            }
+
+            impl Enable for crate::pac::$PER {
+                fn enable(bus: &mut $Bus) {
+                    bus.enr().modify(|_, w| w.$perXen().set_bit());
+                }
+
+                fn disable(bus: &mut $Bus) {
+                    bus.enr().modify(|_, w| w.$perXen().clear_bit());
+                }
+            }
+
+            $(
+                impl Reset for crate::pac::$PER {
+                    fn reset(bus: &mut $Bus) {
+                        bus.rstr().modify(|_, w| w.$perXrst().set_bit());
+                        bus.rstr().modify(|_, w| w.$perXrst().clear_bit());
+                    }
+                }
+            )?
+
+            impl BusClock for crate::pac::$PER {
+                fn clock(clocks: &Clocks) -> Hertz<u32> {
+                    clocks.$pclk()
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(any(feature = "stm32f302", feature = "stm32f303"))]
+bus! {
+    DMA1: (AHB, hclk, dma1en),
+    DMA2: (AHB, hclk, dma2en),
+    GPIOA: (AHB, hclk, iopaen, ioparst),
+    GPIOB: (AHB, hclk, iopben, iopbrst),
+    GPIOC: (AHB, hclk, iopcen, iopcrst),
+    GPIOD: (AHB, hclk, iopden, iopdrst),
+    GPIOE: (AHB, hclk, iopeen, ioperst),
+    GPIOF: (AHB, hclk, iopfen, iopfrst),
+    GPIOG: (AHB, hclk, iopgen, iopgrst),
+    GPIOH: (AHB, hclk, iophen, iophrst),
+    I2C1: (APB1, pclk1, i2c1en, i2c1rst),
+    I2C2: (APB1, pclk1, i2c2en, i2c2rst),
+    PWR: (APB1, pclk1, pwren, pwrrst),
+    SPI1: (APB2, pclk2, spi1en, spi1rst),
+    SPI2: (APB1, pclk1, spi2en, spi2rst),
+    SPI3: (APB1, pclk1, spi3en, spi3rst),
+    SYSCFG: (APB2, pclk2, syscfgen, syscfgrst),
+    TIM16: (APB2, pclk2, tim16en, tim16rst),
+    TIM2: (APB1, pclk1, tim2en, tim2rst),
+    TIM3: (APB1, pclk1, tim3en, tim3rst),
+    TIM4: (APB1, pclk1, tim4en, tim4rst),
+    TIM6: (APB1, pclk1, tim6en, tim6rst),
+    USART1: (APB2, pclk2, usart1en, usart1rst),
+    USART2: (APB1, pclk1, usart2en, usart2rst),
+    USART3: (APB1, pclk1, usart3en, usart3rst),
+}
+
+#[cfg(feature = "stm32f303")]
+bus! {
+    TIM7: (APB1, pclk1, tim7en, tim7rst),
+}
+
+/// High speed internal oscillator (HSI) trimming
+pub struct HSI {
+    _0: (),
+}
+
+impl HSI {
+    /// Returns the factory calibration of the HSI
+    pub fn calibration(&self) -> u8 {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*RCC::ptr()).cr.read().hsical().bits() }
     }
 
-    /// Sets a frequency for the APB2 bus
-    pub fn pclk2<F>(mut self, freq: F) -> Self
-        where F: Into<Hertz<u32>>
-    {
-        self.pclk2 = Some(freq.into().0);
-        self
+    /// Returns the current HSI trimming, 16 is the reset value
+    pub fn trim(&self) -> u8 {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*RCC::ptr()).cr.read().hsitrim().bits() }
     }
 
-    /// Sets the system (core) frequency
-    pub fn sysclk<F>(mut self, freq: F) -> Self
-        where F: Into<Hertz<u32>>
-    {
-        self.sysclk = Some(freq.into().0);
-        self
+    /// Sets the HSI trimming, each step is roughly 40 kHz
+    ///
+    /// Panics if `trim` is higher than 31
+    pub fn set_trim(&mut self, trim: u8) {
+        assert!(trim < 32);
+        // NOTE(unsafe) this proxy grants exclusive access to the HSITRIM bits
+        unsafe { (*RCC::ptr()).cr.modify(|_, w| w.hsitrim().bits(trim)) }
     }
 
-    /// Freezes the clock configuration, making it effective
-    pub fn freeze(self, acr: &mut ACR) -> Clocks {
-        let pllmul = match &self.hse {
-            Some(hse_cfg) => {
-                let hse = hse_cfg.speed / hse_cfg.divider;
-                self.sysclk.unwrap_or(hse) / hse // HSE has settable divider
+    /// Trims the HSI against the LSE and returns the resulting HSI frequency
+    ///
+    /// The RTC clock, routed internally to TIM16 channel 1, must be the LSE
+    /// (see `BackupDomain::rtc_clock`) and the system clock must be derived
+    /// from the HSI. Every trim value is tried by counting TIM16 ticks over
+    /// LSE periods and the closest to 8 MHz is kept. Each trim value takes 6
+    /// captures of 8 LSE periods (~244 us), about 47 ms in total.
+    /// Peripherals clocked from the HSI keep running meanwhile.
+    ///
+    /// TIM16 is reset, used for the measurement and left disabled, whatever
+    /// the caller had configured on it is lost.
+    ///
+    /// `timeout` bounds the number of times each input capture is polled.
+    pub fn calibrate(&mut self,
+                     tim16: &mut TIM16,
+                     apb2: &mut APB2,
+                     _lse: &Lse,
+                     clocks: &Clocks,
+                     timeout: u32)
+                     -> Result<Hertz<u32>, CalibrationError> {
+        let rcc = unsafe { &*RCC::ptr() };
+        let bdcr = rcc.bdcr.read();
+        if !bdcr.rtcsel().is_lse() || bdcr.rtcen().bit_is_clear() {
+            return Err(CalibrationError::RtcClockNotLse);
+        }
+        let cfgr = rcc.cfgr.read();
+        let sws = cfgr.sws().bits();
+        if !(sws == 0b00 || sws == 0b10 && cfgr.pllsrc().is_hsi_div2()) {
+            return Err(CalibrationError::SysclkNotHsi);
+        }
+
+        let mult = if clocks.ppre2() == 1 { 1 } else { 2 };
+        let timclk = TIM16::clock(clocks).0 * mult;
+
+        TIM16::enable(apb2);
+        TIM16::reset(apb2);
+        // TI1_RMP: RTC clock on channel 1
+        tim16.or.write(|w| unsafe { w.bits(0b01) });
+        // CC1S = 0b01: input capture on TI1
+        // IC1PSC = 0b11: capture every 8 edges
+        // (the PAC only exposes the output view of CCMR1)
+        tim16.ccmr1_output.write(|w| unsafe { w.bits(0b11_01) });
+        tim16.ccer.write(|w| w.cc1e().set_bit());
+        tim16.arr.write(|w| unsafe { w.bits(0xFFFF) });
+        tim16.cr1.write(|w| w.cen().set_bit());
+
+        let original = self.trim();
+        let mut best: Option<(u8, u32)> = None;
+        for trim in 0..32 {
+            self.set_trim(trim);
+            let hsi = match measure_hsi(tim16, timclk, timeout) {
+                Some(hsi) => hsi,
+                None => {
+                    self.set_trim(original);
+                    TIM16::disable(apb2);
+                    return Err(CalibrationError::Timeout);
+                }
+            };
+            let error = |hsi: u32| (i64::from(hsi) - i64::from(HSI)).abs();
+            match best {
+                Some((_, best_hsi)) if error(best_hsi) <= error(hsi) => {}
+                _ => best = Some((trim, hsi)),
             }
-            None => (2 * self.sysclk.unwrap_or(HSI)) / HSI, /* HSI is always divided by 2 */
-        };
+        }
+
+        TIM16::disable(apb2);
+
+        // NOTE(unwrap) 32 trim values were measured
+        let (trim, hsi) = best.unwrap();
+        self.set_trim(trim);
+        Ok(Hertz(hsi))
+    }
+}
+
+/// Number of 8 LSE period captures averaged per HSI measurement
+const CAPTURES: u32 = 4;
 
-        let pllmul = cmp::min(cmp::max(pllmul, 2), 16);
-        let pllmul_bits = if pllmul == 2 {
+/// Measures the HSI frequency from the TIM16 ticks counted over LSE periods
+///
+/// `timclk` is the nominal TIM16 clock, i.e. with an 8 MHz HSI
+fn measure_hsi(tim16: &TIM16, timclk: u32, timeout: u32) -> Option<u32> {
+    let capture = || {
+        tim16.sr.modify(|_, w| w.cc1if().clear_bit());
+        if wait_ready(timeout, || tim16.sr.read().cc1if().bit_is_set()) {
+            Some(tim16.ccr1.read().bits() as u16)
+        } else {
             None
+        }
+    };
+
+    // the first capture may span the previous trim value
+    capture()?;
+    let start = capture()?;
+    let mut end = start;
+    let mut ticks = 0;
+    for _ in 0..CAPTURES {
+        let next = capture()?;
+        ticks += u32(next.wrapping_sub(end));
+        end = next;
+    }
+
+    // measured TIM16 clock = ticks * LSE / (8 * CAPTURES)
+    let hsi = u64::from(ticks) * u64::from(LSE) * u64::from(HSI)
+              / (8 * u64::from(CAPTURES) * u64::from(timclk));
+    Some(hsi as u32)
+}
+
+/// HSI calibration error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationError {
+    /// The RTC clock is not the LSE or is disabled
+    RtcClockNotLse,
+    /// The system clock is not derived from the HSI
+    SysclkNotHsi,
+    /// No LSE edge was captured in time
+    Timeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Microcontroller clock output (MCO)
+pub struct MCO {
+    _0: (),
+}
+
+impl MCO {
+    /// Outputs the `source` clock on PA8
+    ///
+    /// The selected oscillator must be running for a clock to appear on the
+    /// pin. The output keeps running when the clock tree is reconfigured with
+    /// `ClockPlan::apply`, but both read-modify-write the RCC_CFGR register:
+    /// the MCO must not be reconfigured from a context that can preempt
+    /// `apply`, or vice versa.
+    pub fn output<PT, OT, OS>(self,
+                              pin: PA8<PT, AltFn<AF0, OT, OS>>,
+                              source: McoSource)
+                              -> Mco<PT, OT, OS>
+        where PT: PullType,
+              OT: OutputType,
+              OS: OutputSpeed
+    {
+        let mut mco = Mco { pin, mco: self };
+        mco.select(source);
+        mco
+    }
+
+    fn cfgr(&mut self) -> &rcc::CFGR {
+        // NOTE(unsafe) this proxy grants exclusive access to the MCO bits,
+        // the rest of the register is written by `ClockPlan::apply` which
+        // only read-modify-writes its own fields
+        unsafe { &(*RCC::ptr()).cfgr }
+    }
+}
+
+/// MCO clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoSource {
+    /// System clock
+    Sysclk,
+    /// Internal 8 MHz RC oscillator
+    Hsi,
+    /// External high speed oscillator
+    Hse,
+    /// PLL output divided by 2
+    PllDiv2,
+    /// Internal 40 kHz RC oscillator
+    Lsi,
+    /// External 32.768 kHz oscillator
+    Lse,
+}
+
+impl McoSource {
+    fn variant(self) -> MCOW {
+        match self {
+            McoSource::Sysclk => MCOW::SYSCLK,
+            McoSource::Hsi => MCOW::HSI,
+            McoSource::Hse => MCOW::HSE,
+            McoSource::PllDiv2 => MCOW::PLL,
+            McoSource::Lsi => MCOW::LSI,
+            McoSource::Lse => MCOW::LSE,
+        }
+    }
+}
+
+/// MCO prescaler
+///
+/// NOTE: only STM32F303xD/E devices implement the prescaler, the bits are
+/// reserved on the others
+#[cfg(feature = "xde")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoPrescaler {
+    /// MCO is divided by 1
+    Div1,
+    /// MCO is divided by 2
+    Div2,
+    /// MCO is divided by 4
+    Div4,
+    /// MCO is divided by 8
+    Div8,
+    /// MCO is divided by 16
+    Div16,
+    /// MCO is divided by 32
+    Div32,
+    /// MCO is divided by 64
+    Div64,
+    /// MCO is divided by 128
+    Div128,
+}
+
+#[cfg(feature = "xde")]
+impl McoPrescaler {
+    fn variant(self) -> MCOPREW {
+        match self {
+            McoPrescaler::Div1 => MCOPREW::DIV1,
+            McoPrescaler::Div2 => MCOPREW::DIV2,
+            McoPrescaler::Div4 => MCOPREW::DIV4,
+            McoPrescaler::Div8 => MCOPREW::DIV8,
+            McoPrescaler::Div16 => MCOPREW::DIV16,
+            McoPrescaler::Div32 => MCOPREW::DIV32,
+            McoPrescaler::Div64 => MCOPREW::DIV64,
+            McoPrescaler::Div128 => MCOPREW::DIV128,
+        }
+    }
+}
+
+/// Clock output on PA8
+pub struct Mco<PT: PullType, OT: OutputType, OS: OutputSpeed> {
+    pin: PA8<PT, AltFn<AF0, OT, OS>>,
+    mco: MCO,
+}
+
+impl<PT: PullType, OT: OutputType, OS: OutputSpeed> Mco<PT, OT, OS> {
+    /// Changes the output clock source
+    pub fn select(&mut self, source: McoSource) {
+        self.mco
+            .cfgr()
+            .modify(|_, w| w.mco().variant(source.variant()));
+    }
+
+    /// Divides the output clock by `prescaler`
+    ///
+    /// `McoSource::PllDiv2` keeps dividing the PLL output by 2 before the
+    /// prescaler.
+    #[cfg(feature = "xde")]
+    pub fn set_prescaler(&mut self, prescaler: McoPrescaler) {
+        self.mco.cfgr().modify(|_, w| {
+                           w.pllnodiv()
+                            .clear_bit()
+                            .mcopre()
+                            .variant(prescaler.variant())
+                       });
+    }
+
+    /// Disables the clock output and releases the pin
+    pub fn release(mut self) -> (MCO, PA8<PT, AltFn<AF0, OT, OS>>) {
+        #[cfg(feature = "xde")]
+        self.set_prescaler(McoPrescaler::Div1);
+        self.mco.cfgr().modify(|_, w| w.mco().no_mco());
+        (self.mco, self.pin)
+    }
+}
+
+/// Backup domain control register
+pub struct BDCR {
+    _0: (),
+}
+
+impl BDCR {
+    /// Enables write access to the backup domain
+    pub fn unlock(self, apb1: &mut APB1, pwr: &mut PWR) -> BackupDomain {
+        PWR::enable(apb1);
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        BackupDomain { _0: () }
+    }
+}
+
+/// Backup domain with write access enabled
+pub struct BackupDomain {
+    _0: (),
+}
+
+impl BackupDomain {
+    /// Starts the LSE and waits for it to stabilize
+    ///
+    /// `timeout` bounds the number of times the ready flag is polled. If the
+    /// LSE is already running, only the drive capability is updated.
+    pub fn enable_lse(&mut self,
+                      drive: LseDrive,
+                      bypass: LseBypass,
+                      timeout: u32)
+                      -> Result<Lse, OscillatorError> {
+        let bdcr = self.bdcr();
+        if bdcr.read().lserdy().is_not_ready() {
+            // LSEBYP can only be written while the LSE is off
+            bdcr.modify(|_, w| w.lseon().clear_bit());
+            bdcr.modify(|_, w| {
+                    w.lsebyp()
+                     .bit(bypass == LseBypass::Enable)
+                     .lsedrv()
+                     .variant(drive.variant())
+                });
+            bdcr.modify(|_, w| w.lseon().set_bit());
         } else {
-            Some(pllmul as u8 - 2)
-        };
+            bdcr.modify(|_, w| w.lsedrv().variant(drive.variant()));
+        }
 
-        let sysclk = match &self.hse {
-            Some(hse_cfg) => {
-                let hse = hse_cfg.speed / hse_cfg.divider;
-                pllmul * hse
-            }
-            None => pllmul * HSI / 2,
+        if wait_ready(timeout, || bdcr.read().lserdy().is_ready()) {
+            Ok(Lse { _0: () })
+        } else {
+            Err(OscillatorError::Timeout)
+        }
+    }
+
+    /// Selects the RTC clock source and enables the RTC clock
+    ///
+    /// The source can only be changed by a backup domain reset once it has
+    /// been selected.
+    pub fn rtc_clock(&mut self,
+                     source: RtcClock)
+                     -> Result<(), BackupDomainError> {
+        let bdcr = self.bdcr();
+        let rtcsel = bdcr.read().rtcsel();
+        let selected = match source {
+            RtcClock::Lse(_) => rtcsel.is_lse(),
+            RtcClock::Lsi(_) => rtcsel.is_lsi(),
+            RtcClock::HseDiv32 => rtcsel.is_hse(),
         };
+        if !rtcsel.is_no_clock() && !selected {
+            return Err(BackupDomainError::RtcClockLocked);
+        }
 
-        assert!(sysclk <= 72_000_000);
-
-        let hpre_bits = self.hclk
-                            .map(|hclk| {
-                                match sysclk / hclk {
-                0 => panic!("Requested HCLK is higher than generated SYSCLK"),
-                1 => 0b0111,
-                2 => 0b1000,
-                3..=5 => 0b1001,
-                6..=11 => 0b1010,
-                12..=39 => 0b1011,
-                40..=95 => 0b1100,
-                96..=191 => 0b1101,
-                192..=383 => 0b1110,
-                _ => 0b1111,
-            }
-                            })
-                            .unwrap_or(0b0111);
-
-        let hclk = sysclk / (1 << (hpre_bits - 0b0111));
-
-        assert!(hclk <= 72_000_000);
-
-        let ppre1_bits = self.pclk1
-                             .map(|pclk1| {
-                                 match hclk / pclk1 {
-                0 => panic!("Requested PCLK1 is higher than generated HCLK"),
-                1 => 0b011,
-                2 => 0b100,
-                3..=5 => 0b101,
-                6..=11 => 0b110,
-                _ => 0b111,
-            }
-                             })
-                             .unwrap_or(0b011);
-
-        let ppre1 = 1 << (ppre1_bits - 0b011);
-        let pclk1 = hclk / u32(ppre1);
-
-        assert!(pclk1 <= 36_000_000);
-
-        let ppre2_bits = self.pclk2
-                             .map(|pclk2| {
-                                 match hclk / pclk2 {
-                0 => panic!("Requested PCLK2 is higher than generated HCLK"),
-                1 => 0b011,
-                2 => 0b100,
-                3..=5 => 0b101,
-                6..=11 => 0b110,
-                _ => 0b111,
-            }
-                             })
-                             .unwrap_or(0b011);
-
-        let ppre2 = 1 << (ppre2_bits - 0b011);
-        let pclk2 = hclk / u32(ppre2);
-
-        assert!(pclk2 <= 72_000_000);
-
-        // adjust flash wait states
-        unsafe {
-            acr.acr().write(|w| {
-                         w.latency().bits(if sysclk <= 24_000_000 {
-                                              0b000
-                                          } else if sysclk <= 48_000_000 {
-                                              0b001
-                                          } else {
-                                              0b010
-                                          })
-                     })
+        bdcr.modify(|_, w| {
+                let w = match source {
+                    RtcClock::Lse(_) => w.rtcsel().lse(),
+                    RtcClock::Lsi(_) => w.rtcsel().lsi(),
+                    RtcClock::HseDiv32 => w.rtcsel().hse(),
+                };
+                w.rtcen().set_bit()
+            });
+
+        Ok(())
+    }
+
+    /// Disables write access to the backup domain
+    pub fn lock(self, pwr: &mut PWR) -> BDCR {
+        pwr.cr.modify(|_, w| w.dbp().clear_bit());
+
+        BDCR { _0: () }
+    }
+
+    fn bdcr(&mut self) -> &rcc::BDCR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).bdcr }
+    }
+}
+
+/// Control/status register
+pub struct CSR {
+    _0: (),
+}
+
+impl CSR {
+    /// Starts the LSI and waits for it to stabilize
+    ///
+    /// `timeout` bounds the number of times the ready flag is polled
+    pub fn enable_lsi(&mut self, timeout: u32) -> Result<Lsi, OscillatorError> {
+        let csr = self.csr();
+        csr.modify(|_, w| w.lsion().set_bit());
+
+        if wait_ready(timeout, || csr.read().lsirdy().bit_is_set()) {
+            Ok(Lsi { _0: () })
+        } else {
+            Err(OscillatorError::Timeout)
+        }
+    }
+
+    /// Returns the causes of the last reset and clears them
+    ///
+    /// Several causes can be reported at once, e.g. a watchdog reset also
+    /// drives the NRST pin.
+    pub fn reset_reason(&mut self) -> ResetReason {
+        let csr = self.csr();
+        let r = csr.read();
+        let reason = ResetReason { low_power: r.lpwrrstf().bit(),
+                                   window_watchdog: r.wwdgrstf().bit(),
+                                   independent_watchdog: r.iwdgrstf().bit(),
+                                   software: r.sftrstf().bit(),
+                                   power_on: r.porrstf().bit(),
+                                   pin: r.pinrstf().bit(),
+                                   option_byte_loader: r.oblrstf().bit(),
+                                   v18_power: r.v18pwrrstf().bit() };
+        csr.modify(|_, w| w.rmvf().set_bit());
+
+        reason
+    }
+
+    fn csr(&mut self) -> &rcc::CSR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).csr }
+    }
+}
+
+/// Set of reset causes read from the control/status register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResetReason {
+    low_power: bool,
+    window_watchdog: bool,
+    independent_watchdog: bool,
+    software: bool,
+    power_on: bool,
+    pin: bool,
+    option_byte_loader: bool,
+    v18_power: bool,
+}
+
+impl ResetReason {
+    /// Power-on or power-down reset
+    pub fn power_on(&self) -> bool {
+        self.power_on
+    }
+
+    /// Reset from the NRST pin
+    pub fn pin(&self) -> bool {
+        self.pin
+    }
+
+    /// Independent watchdog reset
+    pub fn independent_watchdog(&self) -> bool {
+        self.independent_watchdog
+    }
+
+    /// Window watchdog reset
+    pub fn window_watchdog(&self) -> bool {
+        self.window_watchdog
+    }
+
+    /// Software reset, e.g. `SCB::system_reset`
+    pub fn software(&self) -> bool {
+        self.software
+    }
+
+    /// Reset on entering Standby or Stop mode while forbidden by the option
+    /// bytes
+    pub fn low_power(&self) -> bool {
+        self.low_power
+    }
+
+    /// Reset after reloading the option bytes
+    pub fn option_byte_loader(&self) -> bool {
+        self.option_byte_loader
+    }
+
+    /// Reset of the 1.8 V domain
+    pub fn v18_power(&self) -> bool {
+        self.v18_power
+    }
+}
+
+/// Polls `ready` at most `timeout` times
+fn wait_ready<F>(timeout: u32, ready: F) -> bool
+    where F: Fn() -> bool
+{
+    (0..timeout).any(|_| ready())
+}
+
+/// Running 32.768 kHz external low speed oscillator
+pub struct Lse {
+    _0: (),
+}
+
+impl Lse {
+    /// Returns the frequency of the LSE
+    pub fn freq(&self) -> Hertz<u32> {
+        Hertz(LSE)
+    }
+}
+
+/// Running 40 kHz internal low speed RC oscillator
+pub struct Lsi {
+    _0: (),
+}
+
+impl Lsi {
+    /// Returns the nominal frequency of the LSI
+    pub fn freq(&self) -> Hertz<u32> {
+        Hertz(LSI)
+    }
+}
+
+/// LSE oscillator drive capability
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LseDrive {
+    /// Lower driving capability
+    Low,
+    /// Medium low driving capability
+    MediumLow,
+    /// Medium high driving capability
+    MediumHigh,
+    /// Higher driving capability
+    High,
+}
+
+impl LseDrive {
+    fn variant(self) -> LSEDRVW {
+        match self {
+            LseDrive::Low => LSEDRVW::LOW,
+            LseDrive::MediumLow => LSEDRVW::MEDIUMLOW,
+            LseDrive::MediumHigh => LSEDRVW::MEDIUMHIGH,
+            LseDrive::High => LSEDRVW::HIGH,
+        }
+    }
+}
+
+/// LSE bypass selector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LseBypass {
+    /// If the clock driving circuitry is bypassed i.e. using an oscillator
+    Enable,
+    /// If the clock driving circuitry is not bypassed i.e. using a crystal or
+    /// resonator
+    Disable,
+}
+
+/// RTC clock source
+pub enum RtcClock<'a> {
+    /// External low speed oscillator
+    Lse(&'a Lse),
+    /// Internal low speed oscillator
+    Lsi(&'a Lsi),
+    /// HSE divided by 32, the HSE must be running
+    HseDiv32,
+}
+
+/// Low speed oscillator error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OscillatorError {
+    /// The oscillator didn't become ready in time
+    Timeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Backup domain error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackupDomainError {
+    /// Another RTC clock source is already selected
+    RtcClockLocked,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl CFGR {
+    /// Freezes the clock configuration, making it effective
+    ///
+    /// Panics if the requested frequencies cannot be generated exactly or if
+    /// the HSE doesn't start, use [`try_freeze`](#method.try_freeze) to
+    /// handle these cases
+    pub fn freeze(self, acr: &mut ACR) -> Clocks {
+        match self.try_freeze(acr) {
+            Ok(clocks) => clocks,
+            Err(e) => panic!("Invalid clock configuration: {:?}", e),
         }
+    }
 
+    /// Freezes the clock configuration, making it effective
+    ///
+    /// Applies the result of [`plan`](#method.plan), nothing is written to
+    /// the registers if the plan can't be made.
+    pub fn try_freeze(self, acr: &mut ACR) -> Result<Clocks, ClockError> {
+        self.plan()?.apply(acr)
+    }
+}
+
+impl ClockPlan {
+    /// Applies the plan to the hardware
+    ///
+    /// May be called at any time to reconfigure a running clock tree. The
+    /// system clock is switched to the HSI while the HSE and the PLL are
+    /// reconfigured, peripherals and bus masters keep running.
+    ///
+    /// Flash half-cycle access is disabled, and the prefetch buffer is
+    /// enabled if the AHB clock is divided. The MCO selection and the ADC
+    /// prescalers are left untouched.
+    ///
+    /// Every driver that was configured with the previous `Clocks` must then
+    /// be re-timed with the returned ones, see e.g. `Serial::reclock`.
+    ///
+    /// If the HSE doesn't become ready within the timeout set with
+    /// `CFGR::hse_timeout`, it is turned off again and `ClockError::HseTimeout`
+    /// is returned. The system clock is then left on the HSI, with the bus
+    /// prescalers unchanged, and a clock tree without HSE can be applied.
+    pub fn apply(&self, acr: &mut ACR) -> Result<Clocks, ClockError> {
         let rcc = unsafe { &*RCC::ptr() };
+
+        // Run from the HSI while the clock tree is reconfigured, this is a
+        // no-op after reset
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        rcc.cfgr.modify(|_, w| w.sw().hsi());
+        while rcc.cfgr.read().sws().bits() != 0b00 {}
+        rcc.cr.modify(|_, w| w.pllon().clear_bit());
+        while rcc.cr.read().pllrdy().bit_is_set() {}
+
+        // adjust flash wait states, any latency is fine at HSI speed
+        acr.configure(self.flash_latency(), self.hpre() != 1);
+
         // If HSE is available, set it up
-        if let Some(hse_cfg) = &self.hse {
-            if hse_cfg.bypass {
-                // Bypass clock
-                rcc.cr.write(|w| w.hseon().set_bit().hsebyp().set_bit());
-            } else {
-                rcc.cr.write(|w| w.hseon().set_bit());
+        if let Some(bypass) = self.hse {
+            let cr = rcc.cr.read();
+            // HSEBYP can only be written while the HSE is off
+            if cr.hseon().bit_is_set() && cr.hsebyp().bit() != bypass {
+                rcc.cr.modify(|_, w| w.csson().clear_bit().hseon().clear_bit());
+                while rcc.cr.read().hserdy().bit_is_set() {}
             }
+            rcc.cr.modify(|_, w| w.hsebyp().bit(bypass));
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
 
-            // WARNING! Bit 0 in cfgr2 is connected to bit 17 in cfgr (due to
-            // MCU compatibility), if bit 0 is set here it must also
-            // be set in any subsequent write to cfgr and vise-versa
-            rcc.cfgr2.write(|w|
-                w.prediv().bits(hse_cfg.divider as u8 - 1)
-            );
-
-            while rcc.cr.read().hserdy().bit_is_clear() {}
+            if !wait_ready(self.hse_timeout,
+                           || rcc.cr.read().hserdy().bit_is_set())
+            {
+                rcc.cr.modify(|_, w| w.csson().clear_bit().hseon().clear_bit());
+                return Err(ClockError::HseTimeout);
+            }
+        } else if rcc.bdcr.read().rtcsel().is_hse() {
+            // Keep the HSE running for the RTC
+            rcc.cr.modify(|_, w| w.csson().clear_bit());
+        } else {
+            rcc.cr.modify(|_, w| w.csson().clear_bit().hseon().clear_bit());
         }
+        rcc.cr.modify(|_, w| w.csson().bit(self.css));
+
+        let sw_bits = match self.source {
+            SysclkSource::Hsi => 0b00,
+            SysclkSource::Hse => 0b01,
+            SysclkSource::Pll { prediv, pllmul } => {
+                let pllmul_bits = pllmul - 2;
+                // USB clock is PLL / 1.5 at 72 MHz, PLL / 1 at 48 MHz
+                rcc.cfgr.modify(|_, w| w.usbpre().bit(self.sysclk == USB));
+                if self.hse.is_some() {
+                    // WARNING! Bit 0 in cfgr2 is connected to bit 17 in cfgr
+                    // (due to MCU compatibility), if bit 0 is set here it
+                    // must also be set in any subsequent write to cfgr and
+                    // vise-versa
+                    rcc.cfgr2.modify(|_, w| w.prediv().bits(prediv - 1));
+
+                    // HSE as PLL input
+                    rcc.cfgr.modify(|_, w| unsafe {
+                                w.pllsrc()
+                                 .hse_div_prediv()
+                                 .pllmul()
+                                 .bits(pllmul_bits)
+                            });
+                } else {
+                    // HSI / 2 as PLL input
+                    rcc.cfgr.modify(|_, w| unsafe {
+                                w.pllsrc().hsi_div2().pllmul().bits(pllmul_bits)
+                            });
+                }
+
+                rcc.cr.modify(|_, w| w.pllon().set_bit());
+
+                while rcc.cr.read().pllrdy().bit_is_clear() {}
 
-        if let Some(pllmul_bits) = pllmul_bits {
-            // use PLL as source
-
-            if let Some(_) = &self.hse {
-                // HSE as PLL input
-                rcc.cfgr.modify(|_, w| unsafe {
-                            w.pllsrc().hse_div_prediv().pllmul().bits(pllmul_bits)
-                        });
-            } else {
-                // HSI as PLL input
-                rcc.cfgr.write(|w| unsafe { w.pllmul().bits(pllmul_bits) });
+                0b10
             }
+        };
 
-            rcc.cr.modify(|_, w| w.pllon().set_bit());
-
-            while rcc.cr.read().pllrdy().bit_is_clear() {}
-
-            // SW: PLL selected as system clock
-            rcc.cfgr.modify(|_, w| unsafe {
-                        w.ppre2()
-                         .bits(ppre2_bits)
-                         .ppre1()
-                         .bits(ppre1_bits)
-                         .hpre()
-                         .bits(hpre_bits)
-                         .sw()
-                         .bits(0b10)
-                    });
-        } else {
-            let sw_bits = if let Some(_) = &self.hse {
-                // use HSE as source
-                0b01
-            } else {
-                // use HSI as source
-                0b00
-            };
+        rcc.cfgr.modify(|_, w| unsafe {
+                    w.ppre2()
+                     .bits(self.ppre2_bits)
+                     .ppre1()
+                     .bits(self.ppre1_bits)
+                     .hpre()
+                     .bits(self.hpre_bits)
+                     .sw()
+                     .bits(sw_bits)
+                });
 
-            rcc.cfgr.write(|w| unsafe {
-                        w.ppre2()
-                         .bits(ppre2_bits)
-                         .ppre1()
-                         .bits(ppre1_bits)
-                         .hpre()
-                         .bits(hpre_bits)
-                         .sw()
-                         .bits(sw_bits)
-                    });
-        }
+        while rcc.cfgr.read().sws().bits() != sw_bits {}
 
-        // Running from HSE or PLL fed by HSE, disable HSI
-        if let Some(_) = &self.hse {
+        let kernel = &self.kernel;
+        rcc.cfgr3.modify(|_, w| {
+                     w.usart1sw()
+                      .bits(kernel.usart[0].bits())
+                      .usart2sw()
+                      .bits(kernel.usart[1].bits())
+                      .usart3sw()
+                      .bits(kernel.usart[2].bits())
+                      .i2c1sw()
+                      .bit(kernel.i2c[0] == Some(I2cClock::Sysclk))
+                      .i2c2sw()
+                      .bit(kernel.i2c[1] == Some(I2cClock::Sysclk))
+                      .i2c3sw()
+                      .bit(kernel.i2c[2] == Some(I2cClock::Sysclk))
+                      .tim1sw()
+                      .bit(kernel.tim1 == TimClock::Pll)
+                 });
+        #[cfg(feature = "stm32f303")]
+        rcc.cfgr3.modify(|_, w| w.tim8sw().bit(kernel.tim8 == TimClock::Pll));
+
+        // Running from HSE or PLL fed by HSE, disable HSI unless a peripheral
+        // was explicitly set to use it, I2C drivers turn it back on by
+        // default
+        let hsi_used = kernel.usart.contains(&UsartClock::Hsi)
+                       || kernel.i2c.contains(&Some(I2cClock::Hsi));
+        if self.hse.is_some() && !hsi_used {
             rcc.cr.modify(|_, w| w.hsion().clear_bit());
         }
 
-        Clocks { hclk: Hertz(hclk),
-                 pclk1: Hertz(pclk1),
-                 pclk2: Hertz(pclk2),
-                 ppre1,
-                 ppre2,
-                 sysclk: Hertz(sysclk) }
+        Ok(Clocks { hclk: Hertz(self.hclk),
+                    pclk1: Hertz(self.pclk1),
+                    pclk2: Hertz(self.pclk2),
+                    ppre1: self.ppre1,
+                    ppre2: self.ppre2,
+                    sysclk: Hertz(self.sysclk),
+                    usartclk: [self.usartclk(0),
+                               self.usartclk(1),
+                               self.usartclk(2)],
+                    i2cclk: [self.i2cclk(0), self.i2cclk(1), self.i2cclk(2)],
+                    tim1clk: self.timclk(kernel.tim1),
+                    #[cfg(feature = "stm32f303")]
+                    tim8clk: self.timclk(kernel.tim8),
+                    usbclk: self.usbclk(),
+                    lse: if self.lse { Some(Hertz(LSE)) } else { None },
+                    lsi: if self.lsi { Some(Hertz(LSI)) } else { None },
+                    kernel: self.kernel })
+    }
+}
+
+/// Turns the HSI on, if it isn't already, and waits for it to be ready
+///
+/// For the peripherals that run from the HSI whatever the system clock.
+pub(crate) fn enable_hsi() {
+    // NOTE(unsafe) read-modify-write of HSION, which is otherwise only
+    // written by `ClockPlan::apply` and must not preempt it
+    let rcc = unsafe { &*RCC::ptr() };
+    if rcc.cr.read().hsion().bit_is_clear() {
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
     }
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+}
+
+/// Returns `true` if the clock security system detected an HSE failure
+///
+/// Meant to be called from the NMI handler.
+pub fn css_failure() -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { (*RCC::ptr()).cir.read().cssf().bit_is_set() }
+}
+
+/// Clears the clock security system failure flag
+///
+/// Must be called from the NMI handler, otherwise the NMI is raised again
+/// as soon as the handler returns.
+pub fn clear_css_failure() {
+    // NOTE(unsafe) the CIR register is not used anywhere else in this crate
+    unsafe { (*RCC::ptr()).cir.modify(|_, w| w.cssc().set_bit()) }
 }
 
 /// Frozen clock frequencies
 ///
-/// The existence of this value indicates that the clock configuration can no
-/// longer be changed
+/// Snapshot of the clock tree that drivers are timed with. It only changes
+/// when a [`ClockPlan`] is applied again, after which the drivers have to be
+/// re-timed with the new `Clocks`.
+///
+/// [`ClockPlan`]: struct.ClockPlan.html
 #[derive(Clone, Copy)]
 pub struct Clocks {
     hclk: Hertz<u32>,
     pclk1: Hertz<u32>,
     pclk2: Hertz<u32>,
     ppre1: u8,
-    // TODO remove `allow`
-    #[allow(dead_code)]
     ppre2: u8,
     sysclk: Hertz<u32>,
+    usartclk: [Hertz<u32>; 3],
+    i2cclk: [Hertz<u32>; 3],
+    tim1clk: Hertz<u32>,
+    #[cfg(feature = "stm32f303")]
+    tim8clk: Hertz<u32>,
+    usbclk: Option<Hertz<u32>>,
+    lse: Option<Hertz<u32>>,
+    lsi: Option<Hertz<u32>>,
+    kernel: KernelClocks,
 }
 
 impl Clocks {
+    /// Re-plans the clock tree on the HSI after an HSE failure
+    ///
+    /// The system clock is brought as close as possible to its current
+    /// frequency using the PLL fed by HSI / 2 (64 MHz at most). Bus and
+    /// peripheral clocks are then derived the same way
+    /// [`CFGR::freeze`](struct.CFGR.html#method.freeze) does by default,
+    /// kernel clock selections are kept but TIM1 and TIM8 fall back to PCLK2
+    /// if the PLL is not used, and the USB clock is lost.
+    ///
+    /// The returned `Clocks` must be used to re-time the drivers that were
+    /// configured with `self`.
+    pub fn fall_back_to_hsi(&self, acr: &mut ACR) -> Clocks {
+        // HSI / 2 can only be multiplied in steps of 4 MHz
+        let step = HSI / 2;
+        let sysclk = cmp::min(self.sysclk.0, step * 16) / step * step;
+        let sysclk = if sysclk >= PLL_OUT_MIN { Some(sysclk) } else { None };
+
+        let mut kernel = self.kernel;
+        if sysclk.is_none() {
+            kernel.tim1 = TimClock::Pclk2;
+            kernel.tim8 = TimClock::Pclk2;
+        }
+
+        let mut cfgr = CFGR::new();
+        cfgr.sysclk = sysclk;
+        cfgr.lse = self.lse.is_some();
+        cfgr.lsi = self.lsi.is_some();
+        cfgr.kernel = kernel;
+        // the HSI plan doesn't wait for the HSE, so it can't time out
+        match cfgr.plan().and_then(|plan| plan.apply(acr)) {
+            Ok(clocks) => clocks,
+            Err(e) => panic!("Invalid clock configuration: {:?}", e),
+        }
+    }
+
     /// Returns the frequency of the AHB
     pub fn hclk(&self) -> Hertz<u32> {
         self.hclk
@@ -442,8 +1076,6 @@ impl Clocks {
         self.ppre1
     }
 
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn ppre2(&self) -> u8 {
         self.ppre2
     }
@@ -452,4 +1084,79 @@ impl Clocks {
     pub fn sysclk(&self) -> Hertz<u32> {
         self.sysclk
     }
+
+    /// Returns the flash wait states required by the system clock
+    pub fn flash_latency(&self) -> u8 {
+        flash_latency(self.sysclk.0)
+    }
+
+    /// Returns the USB clock frequency
+    ///
+    /// `None` unless the clocks were frozen with
+    /// [`require_usb_clock`](struct.CFGR.html#method.require_usb_clock)
+    pub fn usbclk(&self) -> Option<Hertz<u32>> {
+        self.usbclk
+    }
+
+    /// Returns the frequency of the LSE
+    ///
+    /// `None` unless the clocks were frozen with
+    /// [`lse`](struct.CFGR.html#method.lse)
+    pub fn lse(&self) -> Option<Hertz<u32>> {
+        self.lse
+    }
+
+    /// Returns the nominal frequency of the LSI
+    ///
+    /// `None` unless the clocks were frozen with
+    /// [`lsi`](struct.CFGR.html#method.lsi)
+    pub fn lsi(&self) -> Option<Hertz<u32>> {
+        self.lsi
+    }
+
+    /// Returns the kernel clock frequency of USART1
+    pub fn usart1clk(&self) -> Hertz<u32> {
+        self.usartclk[0]
+    }
+
+    /// Returns the kernel clock frequency of USART2
+    pub fn usart2clk(&self) -> Hertz<u32> {
+        self.usartclk[1]
+    }
+
+    /// Returns the kernel clock frequency of USART3
+    pub fn usart3clk(&self) -> Hertz<u32> {
+        self.usartclk[2]
+    }
+
+    /// Returns the kernel clock frequency of I2C1
+    pub fn i2c1clk(&self) -> Hertz<u32> {
+        self.i2cclk[0]
+    }
+
+    /// Returns the kernel clock frequency of I2C2
+    pub fn i2c2clk(&self) -> Hertz<u32> {
+        self.i2cclk[1]
+    }
+
+    /// Returns the kernel clock frequency of I2C3
+    pub fn i2c3clk(&self) -> Hertz<u32> {
+        self.i2cclk[2]
+    }
+
+    /// Returns `true` if I2C`i + 1` runs from the HSI
+    pub(crate) fn i2c_hsi(&self, i: usize) -> bool {
+        self.kernel.i2c[i] != Some(I2cClock::Sysclk)
+    }
+
+    /// Returns the kernel clock frequency of TIM1
+    pub fn tim1clk(&self) -> Hertz<u32> {
+        self.tim1clk
+    }
+
+    /// Returns the kernel clock frequency of TIM8
+    #[cfg(feature = "stm32f303")]
+    pub fn tim8clk(&self) -> Hertz<u32> {
+        self.tim8clk
+    }
 }
@@ -0,0 +1,1038 @@
+//! Clock tree planning
+//!
+//! Computes the prescaler and PLL settings of a clock tree from the
+//! requested frequencies. Nothing in here touches a register, the resulting
+//! [`ClockPlan`] is written to the hardware by `ClockPlan::apply`.
+//!
+//! [`ClockPlan`]: struct.ClockPlan.html
+
+use cast::u32;
+
+use super::{Lse, Lsi};
+use crate::time::Hertz;
+
+/// HSE Configuration of clock, divider and bypass
+#[derive(Clone)]
+struct HseConfig {
+    /// Clock speed of HSE
+    speed: u32,
+    /// Divider to be used, PLL input will be HSE / divider
+    divider: u32,
+    /// If the clock driving circuitry is bypassed i.e. using an oscillator,
+    /// not a crystal or resonator
+    bypass: bool,
+}
+
+/// HSE bypass selector
+pub enum HseBypass {
+    /// If the clock driving circuitry is bypassed i.e. using an oscillator
+    Enable,
+    /// If the clock driving circuitry is not bypassed i.e. using a crystal or
+    /// resonator
+    Disable,
+}
+
+/// HSE divide selector
+pub enum HseDivider {
+    /// Do not divide HSE clock
+    NoDivision,
+    /// Divide HSE clock by 2
+    DivideBy2,
+    /// Divide HSE clock by 3
+    DivideBy3,
+    /// Divide HSE clock by 4
+    DivideBy4,
+    /// Divide HSE clock by 5
+    DivideBy5,
+    /// Divide HSE clock by 6
+    DivideBy6,
+    /// Divide HSE clock by 7
+    DivideBy7,
+    /// Divide HSE clock by 8
+    DivideBy8,
+    /// Divide HSE clock by 9
+    DivideBy9,
+    /// Divide HSE clock by 10
+    DivideBy10,
+    /// Divide HSE clock by 11
+    DivideBy11,
+    /// Divide HSE clock by 12
+    DivideBy12,
+    /// Divide HSE clock by 13
+    DivideBy13,
+    /// Divide HSE clock by 14
+    DivideBy14,
+    /// Divide HSE clock by 15
+    DivideBy15,
+    /// Divide HSE clock by 16
+    DivideBy16,
+}
+
+pub(super) const HSI: u32 = 8_000_000; // Hz
+pub(super) const LSE: u32 = 32_768; // Hz
+pub(super) const LSI: u32 = 40_000; // Hz
+pub(super) const USB: u32 = 48_000_000; // Hz
+
+/// Default number of times the HSE ready flag is polled, over 100 ms when
+/// running from the HSI
+const HSE_TIMEOUT: u32 = 1_000_000;
+
+/// USART kernel clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsartClock {
+    /// Clock of the APB the USART is on (reset value)
+    Pclk,
+    /// System clock
+    Sysclk,
+    /// LSE, requires [`CFGR::lse`](struct.CFGR.html#method.lse)
+    Lse,
+    /// HSI
+    Hsi,
+}
+
+impl UsartClock {
+    pub(super) fn bits(self) -> u8 {
+        match self {
+            UsartClock::Pclk => 0b00,
+            UsartClock::Sysclk => 0b01,
+            UsartClock::Lse => 0b10,
+            UsartClock::Hsi => 0b11,
+        }
+    }
+}
+
+/// I2C kernel clock source
+///
+/// I2C runs from the HSI unless another source is selected, and the I2C
+/// driver turns the HSI on if needed. Selecting `Hsi` explicitly also keeps
+/// the HSI on when the clock tree is applied with an HSE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum I2cClock {
+    /// HSI (reset value)
+    Hsi,
+    /// System clock
+    Sysclk,
+}
+
+/// TIM1 / TIM8 kernel clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimClock {
+    /// APB2 timer clock (reset value)
+    Pclk2,
+    /// PLL output x2, up to 144 MHz
+    ///
+    /// Only available when the PLL drives the system clock and neither the
+    /// AHB nor the APB2 clock is divided
+    Pll,
+}
+
+/// Kernel clock selection of the peripherals that don't have to run from
+/// their bus clock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct KernelClocks {
+    pub(super) usart: [UsartClock; 3],
+    /// `None` is the HSI, without keeping it on when the HSE is used
+    pub(super) i2c: [Option<I2cClock>; 3],
+    pub(super) tim1: TimClock,
+    pub(super) tim8: TimClock,
+}
+
+/// Clock configuration
+///
+/// Can be cloned to [`plan`](#method.plan) several clock trees from the same
+/// settings, e.g. a fast and a low power one to switch between at runtime.
+#[derive(Clone)]
+pub struct CFGR {
+    hse: Option<HseConfig>,
+    hse_timeout: u32,
+    hclk: Option<u32>,
+    pclk1: Option<u32>,
+    pclk2: Option<u32>,
+    pub(super) sysclk: Option<u32>,
+    usb: bool,
+    css: bool,
+    pub(super) lse: bool,
+    pub(super) lsi: bool,
+    pub(super) kernel: KernelClocks,
+}
+
+impl CFGR {
+    /// Reset configuration: the system clock runs from the HSI
+    pub(super) fn new() -> Self {
+        CFGR { hse: None,
+               hse_timeout: HSE_TIMEOUT,
+               hclk: None,
+               pclk1: None,
+               pclk2: None,
+               sysclk: None,
+               usb: false,
+               css: false,
+               lse: false,
+               lsi: false,
+               kernel: KernelClocks { usart: [UsartClock::Pclk; 3],
+                                      i2c: [None; 3],
+                                      tim1: TimClock::Pclk2,
+                                      tim8: TimClock::Pclk2 } }
+    }
+
+    /// Sets a HseConfig that checks that the HSE divider is valid and if the
+    /// clock is in bypass mode
+    ///
+    /// The divider (PREDIV) only applies to the PLL input
+    pub fn hse<F>(mut self,
+                  freq: F,
+                  divider: HseDivider,
+                  bypass: HseBypass)
+                  -> Self
+        where F: Into<Hertz<u32>>
+    {
+        self.hse = Some(HseConfig { speed: freq.into().0,
+                                    divider: match divider {
+                                        HseDivider::NoDivision => 1,
+                                        HseDivider::DivideBy2 => 2,
+                                        HseDivider::DivideBy3 => 3,
+                                        HseDivider::DivideBy4 => 4,
+                                        HseDivider::DivideBy5 => 5,
+                                        HseDivider::DivideBy6 => 6,
+                                        HseDivider::DivideBy7 => 7,
+                                        HseDivider::DivideBy8 => 8,
+                                        HseDivider::DivideBy9 => 9,
+                                        HseDivider::DivideBy10 => 10,
+                                        HseDivider::DivideBy11 => 11,
+                                        HseDivider::DivideBy12 => 12,
+                                        HseDivider::DivideBy13 => 13,
+                                        HseDivider::DivideBy14 => 14,
+                                        HseDivider::DivideBy15 => 15,
+                                        HseDivider::DivideBy16 => 16,
+                                    },
+                                    bypass: match bypass {
+                                        HseBypass::Disable => false,
+                                        HseBypass::Enable => true,
+                                    } });
+
+        self
+    }
+
+    /// Bounds the number of times the HSE ready flag is polled when the plan
+    /// is applied, a missing or dead crystal then results in
+    /// `ClockError::HseTimeout`
+    pub fn hse_timeout(mut self, timeout: u32) -> Self {
+        self.hse_timeout = timeout;
+        self
+    }
+
+    /// Sets a frequency for the AHB bus
+    pub fn hclk<F>(mut self, freq: F) -> Self
+        where F: Into<Hertz<u32>>
+    {
+        self.hclk = Some(freq.into().0);
+        self
+    }
+
+    /// Sets a frequency for the APB1 bus
+    pub fn pclk1<F>(mut self, freq: F) -> Self
+        where F: Into<Hertz<u32>>
+    {
+        self.pclk1 = Some(freq.into().0);
+        self
+    }
+
+    /// Sets a frequency for the APB2 bus
+    pub fn pclk2<F>(mut self, freq: F) -> Self
+        where F: Into<Hertz<u32>>
+    {
+        self.pclk2 = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the system (core) frequency
+    pub fn sysclk<F>(mut self, freq: F) -> Self
+        where F: Into<Hertz<u32>>
+    {
+        self.sysclk = Some(freq.into().0);
+        self
+    }
+
+    /// Requires a 48 MHz USB clock
+    ///
+    /// Restricts the system clock to 48 or 72 MHz generated by the PLL from
+    /// the HSE, 72 MHz is preferred if [`sysclk`](#method.sysclk) isn't set
+    pub fn require_usb_clock(mut self) -> Self {
+        self.usb = true;
+        self
+    }
+
+    /// Enables the clock security system (CSS), requires [`hse`](#method.hse)
+    ///
+    /// If the HSE fails, the hardware switches the system clock to the HSI,
+    /// stops the HSE and the PLL and raises an NMI. The NMI handler must
+    /// clear the failure with [`clear_css_failure`](fn.clear_css_failure.html),
+    /// after which [`Clocks::fall_back_to_hsi`] brings the rest of the clock
+    /// tree back in line.
+    ///
+    /// [`Clocks::fall_back_to_hsi`]: struct.Clocks.html#method.fall_back_to_hsi
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Makes the running LSE available to the clock tree
+    pub fn lse(mut self, _lse: &Lse) -> Self {
+        self.lse = true;
+        self
+    }
+
+    /// Makes the running LSI available to the clock tree
+    pub fn lsi(mut self, _lsi: &Lsi) -> Self {
+        self.lsi = true;
+        self
+    }
+
+    /// Sets the kernel clock source of USART1
+    pub fn usart1_clock(mut self, source: UsartClock) -> Self {
+        self.kernel.usart[0] = source;
+        self
+    }
+
+    /// Sets the kernel clock source of USART2
+    pub fn usart2_clock(mut self, source: UsartClock) -> Self {
+        self.kernel.usart[1] = source;
+        self
+    }
+
+    /// Sets the kernel clock source of USART3
+    pub fn usart3_clock(mut self, source: UsartClock) -> Self {
+        self.kernel.usart[2] = source;
+        self
+    }
+
+    /// Sets the kernel clock source of I2C1
+    pub fn i2c1_clock(mut self, source: I2cClock) -> Self {
+        self.kernel.i2c[0] = Some(source);
+        self
+    }
+
+    /// Sets the kernel clock source of I2C2
+    pub fn i2c2_clock(mut self, source: I2cClock) -> Self {
+        self.kernel.i2c[1] = Some(source);
+        self
+    }
+
+    /// Sets the kernel clock source of I2C3
+    pub fn i2c3_clock(mut self, source: I2cClock) -> Self {
+        self.kernel.i2c[2] = Some(source);
+        self
+    }
+
+    /// Sets the kernel clock source of TIM1
+    pub fn tim1_clock(mut self, source: TimClock) -> Self {
+        self.kernel.tim1 = source;
+        self
+    }
+
+    /// Sets the kernel clock source of TIM8
+    #[cfg(feature = "stm32f303")]
+    pub fn tim8_clock(mut self, source: TimClock) -> Self {
+        self.kernel.tim8 = source;
+        self
+    }
+
+    /// Computes the clock tree for the requested frequencies without
+    /// touching any register
+    ///
+    /// Searches the PREDIV, PLLMUL, HPRE, PPRE1 and PPRE2 settings for a
+    /// combination that generates every requested frequency exactly. The
+    /// HSE divider passed to [`hse`](#method.hse) is preferred, other
+    /// dividers are only used when it does not lead to an exact match.
+    /// Unconstrained bus clocks run as fast as allowed.
+    pub fn plan(&self) -> Result<ClockPlan, ClockError> {
+        let (source, sysclk) = self.plan_sysclk()?;
+
+        let (hpre_bits, hclk) = match self.hclk {
+            Some(hclk) => HPRE.iter()
+                              .find(|&&(div, _)| {
+                                  sysclk % div == 0 && sysclk / div == hclk
+                              })
+                              .map(|&(_, bits)| (bits, hclk))
+                              .ok_or(ClockError::HclkUnreachable)?,
+            None => (0b0000, sysclk),
+        };
+
+        let (ppre1_bits, ppre1, pclk1) =
+            plan_pclk(hclk, self.pclk1, PCLK1_MAX)
+                .ok_or(ClockError::Pclk1Unreachable)?;
+        let (ppre2_bits, ppre2, pclk2) =
+            plan_pclk(hclk, self.pclk2, PCLK2_MAX)
+                .ok_or(ClockError::Pclk2Unreachable)?;
+
+        let tim_pll = self.kernel.tim1 == TimClock::Pll
+                      || self.kernel.tim8 == TimClock::Pll;
+        let pll_undivided = match source {
+            SysclkSource::Pll { .. } => hpre_bits == 0b0000 && ppre2 == 1,
+            _ => false,
+        };
+        if tim_pll && !pll_undivided {
+            return Err(ClockError::TimClockUnreachable);
+        }
+
+        Ok(ClockPlan { hse: self.hse.as_ref().map(|hse_cfg| hse_cfg.bypass),
+                       hse_timeout: self.hse_timeout,
+                       source,
+                       usb: self.usb,
+                       css: self.css,
+                       lse: self.lse,
+                       lsi: self.lsi,
+                       kernel: self.kernel,
+                       hpre_bits,
+                       ppre1_bits,
+                       ppre2_bits,
+                       ppre1,
+                       ppre2,
+                       sysclk,
+                       hclk,
+                       pclk1,
+                       pclk2 })
+    }
+
+    fn plan_sysclk(&self) -> Result<(SysclkSource, u32), ClockError> {
+        if let Some(hse_cfg) = &self.hse {
+            let min = if hse_cfg.bypass { 1_000_000 } else { 4_000_000 };
+            if hse_cfg.speed < min || hse_cfg.speed > 32_000_000 {
+                return Err(ClockError::HseOutOfRange);
+            }
+        }
+
+        if !self.lse && self.kernel.usart.contains(&UsartClock::Lse) {
+            return Err(ClockError::LseNotRunning);
+        }
+
+        if self.css && self.hse.is_none() {
+            return Err(ClockError::CssWithoutHse);
+        }
+
+        if self.usb {
+            return self.plan_usb();
+        }
+
+        let (input, sysclk) = match (&self.hse, self.sysclk) {
+            (None, None) => return Ok((SysclkSource::Hsi, HSI)),
+            (Some(hse_cfg), None) => {
+                return Ok((SysclkSource::Hse, hse_cfg.speed))
+            }
+            (None, Some(sysclk)) if sysclk == HSI => {
+                return Ok((SysclkSource::Hsi, HSI))
+            }
+            (Some(hse_cfg), Some(sysclk)) if sysclk == hse_cfg.speed => {
+                return Ok((SysclkSource::Hse, sysclk))
+            }
+            (hse, Some(sysclk)) => (hse.as_ref(), sysclk),
+        };
+
+        if sysclk > SYSCLK_MAX {
+            return Err(ClockError::SysclkTooHigh);
+        }
+
+        plan_pll(input, sysclk).map(|source| (source, sysclk))
+                               .ok_or(ClockError::SysclkUnreachable)
+    }
+
+    /// The USB clock is the PLL output divided by 1 or 1.5, so the PLL has to
+    /// run at 48 or 72 MHz from the HSE
+    fn plan_usb(&self) -> Result<(SysclkSource, u32), ClockError> {
+        let hse = self.hse.as_ref().ok_or(ClockError::UsbClockUnreachable)?;
+        let targets: &[u32] = match self.sysclk {
+            None => &[72_000_000, 48_000_000],
+            Some(72_000_000) => &[72_000_000],
+            Some(48_000_000) => &[48_000_000],
+            Some(_) => return Err(ClockError::UsbClockUnreachable),
+        };
+
+        targets.iter()
+               .filter_map(|&sysclk| {
+                   plan_pll(Some(hse), sysclk).map(|source| (source, sysclk))
+               })
+               .next()
+               .ok_or(ClockError::UsbClockUnreachable)
+    }
+}
+
+/// Finds the PREDIV and PLLMUL settings that generate `sysclk` from the HSE,
+/// or from the HSI if `hse` is `None`
+fn plan_pll(hse: Option<&HseConfig>, sysclk: u32) -> Option<SysclkSource> {
+    if sysclk < PLL_OUT_MIN || sysclk > SYSCLK_MAX {
+        return None;
+    }
+
+    // PLL source frequency and PREDIV candidates, HSI is always divided by 2
+    let (src, preferred, predivs) = match hse {
+        Some(hse_cfg) => (hse_cfg.speed, hse_cfg.divider, 1..=16),
+        None => (HSI, 2, 2..=2),
+    };
+
+    let candidates = core::iter::once(preferred).chain(predivs);
+    for prediv in candidates {
+        // PLL input clock must be within 1 - 24 MHz
+        if src < PLL_IN_MIN * prediv || src > PLL_IN_MAX * prediv {
+            continue;
+        }
+        for pllmul in 2..=16 {
+            if src * pllmul == sysclk * prediv {
+                return Some(SysclkSource::Pll { prediv: prediv as u8,
+                                                pllmul: pllmul as u8 });
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds the APB prescaler for the `requested` frequency, or the smallest one
+/// that keeps the bus below `max`
+///
+/// Returns (PPRE bits, divider, frequency)
+fn plan_pclk(hclk: u32,
+             requested: Option<u32>,
+             max: u32)
+             -> Option<(u8, u8, u32)> {
+    PPRE.iter()
+        .map(|&(div, bits)| (bits, div as u8, hclk / div))
+        .find(|&(_, div, pclk)| match requested {
+            Some(requested) => {
+                hclk % u32(div) == 0 && pclk == requested && pclk <= max
+            }
+            None => pclk <= max,
+        })
+}
+
+/// (divider, HPRE bits)
+const HPRE: [(u32, u8); 9] = [(1, 0b0000),
+                              (2, 0b1000),
+                              (4, 0b1001),
+                              (8, 0b1010),
+                              (16, 0b1011),
+                              (64, 0b1100),
+                              (128, 0b1101),
+                              (256, 0b1110),
+                              (512, 0b1111)];
+
+/// (divider, PPREx bits)
+const PPRE: [(u32, u8); 5] =
+    [(1, 0b000), (2, 0b100), (4, 0b101), (8, 0b110), (16, 0b111)];
+
+const SYSCLK_MAX: u32 = 72_000_000;
+const PCLK1_MAX: u32 = 36_000_000;
+const PCLK2_MAX: u32 = 72_000_000;
+const PLL_IN_MIN: u32 = 1_000_000;
+const PLL_IN_MAX: u32 = 24_000_000;
+pub(super) const PLL_OUT_MIN: u32 = 16_000_000;
+
+/// System clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SysclkSource {
+    /// Internal 8 MHz RC oscillator
+    Hsi,
+    /// External oscillator, undivided
+    Hse,
+    /// PLL fed by HSE / `prediv`, or by HSI / 2 (`prediv` is then 2)
+    Pll {
+        /// PLL input divider
+        prediv: u8,
+        /// PLL multiplication factor
+        pllmul: u8,
+    },
+}
+
+/// Validated clock tree settings and the resulting frequencies
+///
+/// Returned by [`CFGR::plan`](struct.CFGR.html#method.plan), freezing the
+/// `CFGR` applies it to the hardware. A plan can also be applied at runtime
+/// to reconfigure the clock tree, see [`apply`](#method.apply).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockPlan {
+    /// HSE bypass, if HSE is used
+    pub(super) hse: Option<bool>,
+    /// Number of times the HSE ready flag is polled
+    pub(super) hse_timeout: u32,
+    pub(super) source: SysclkSource,
+    /// USB clock required
+    pub(super) usb: bool,
+    /// Clock security system enabled
+    pub(super) css: bool,
+    pub(super) lse: bool,
+    pub(super) lsi: bool,
+    pub(super) kernel: KernelClocks,
+    pub(super) hpre_bits: u8,
+    pub(super) ppre1_bits: u8,
+    pub(super) ppre2_bits: u8,
+    pub(super) ppre1: u8,
+    pub(super) ppre2: u8,
+    pub(super) sysclk: u32,
+    pub(super) hclk: u32,
+    pub(super) pclk1: u32,
+    pub(super) pclk2: u32,
+}
+
+impl ClockPlan {
+    /// Returns the system clock source
+    pub fn source(&self) -> SysclkSource {
+        self.source
+    }
+
+    /// Returns the AHB prescaler
+    pub fn hpre(&self) -> u16 {
+        HPRE.iter()
+            .find(|&&(_, bits)| bits == self.hpre_bits)
+            .map(|&(div, _)| div as u16)
+            .unwrap_or(1)
+    }
+
+    /// Returns the APB1 prescaler
+    pub fn ppre1(&self) -> u8 {
+        self.ppre1
+    }
+
+    /// Returns the APB2 prescaler
+    pub fn ppre2(&self) -> u8 {
+        self.ppre2
+    }
+
+    /// Returns the flash wait states required by the system clock
+    pub fn flash_latency(&self) -> u8 {
+        flash_latency(self.sysclk)
+    }
+
+    /// Returns the system (core) frequency
+    pub fn sysclk(&self) -> Hertz<u32> {
+        Hertz(self.sysclk)
+    }
+
+    /// Returns the frequency of the AHB
+    pub fn hclk(&self) -> Hertz<u32> {
+        Hertz(self.hclk)
+    }
+
+    /// Returns the frequency of the APB1
+    pub fn pclk1(&self) -> Hertz<u32> {
+        Hertz(self.pclk1)
+    }
+
+    /// Returns the frequency of the APB2
+    pub fn pclk2(&self) -> Hertz<u32> {
+        Hertz(self.pclk2)
+    }
+
+    /// Returns the USB clock frequency, if it was required
+    pub fn usbclk(&self) -> Option<Hertz<u32>> {
+        if self.usb {
+            Some(Hertz(USB))
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn usartclk(&self, usart: usize) -> Hertz<u32> {
+        Hertz(match self.kernel.usart[usart] {
+                  // USART1 is on APB2, the others on APB1
+                  UsartClock::Pclk if usart == 0 => self.pclk2,
+                  UsartClock::Pclk => self.pclk1,
+                  UsartClock::Sysclk => self.sysclk,
+                  UsartClock::Lse => LSE,
+                  UsartClock::Hsi => HSI,
+              })
+    }
+
+    pub(super) fn i2cclk(&self, i2c: usize) -> Hertz<u32> {
+        Hertz(match self.kernel.i2c[i2c] {
+                  None | Some(I2cClock::Hsi) => HSI,
+                  Some(I2cClock::Sysclk) => self.sysclk,
+              })
+    }
+
+    pub(super) fn timclk(&self, source: TimClock) -> Hertz<u32> {
+        Hertz(match source {
+                  TimClock::Pclk2 if self.ppre2 == 1 => self.pclk2,
+                  TimClock::Pclk2 => 2 * self.pclk2,
+                  TimClock::Pll => 2 * self.sysclk,
+              })
+    }
+}
+
+/// Returns the flash wait states required by a system clock of `sysclk` Hz
+pub(super) fn flash_latency(sysclk: u32) -> u8 {
+    if sysclk <= 24_000_000 {
+        0
+    } else if sysclk <= 48_000_000 {
+        1
+    } else {
+        2
+    }
+}
+
+/// Clock configuration error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockError {
+    /// HSE frequency is out of the 4 - 32 MHz range (1 - 32 MHz in bypass
+    /// mode)
+    HseOutOfRange,
+    /// Requested SYSCLK is higher than 72 MHz
+    SysclkTooHigh,
+    /// Requested SYSCLK can't be generated exactly from HSI or HSE
+    SysclkUnreachable,
+    /// Requested HCLK is not SYSCLK divided by a valid AHB prescaler
+    HclkUnreachable,
+    /// Requested PCLK1 is not HCLK divided by a valid APB1 prescaler, or is
+    /// higher than 36 MHz
+    Pclk1Unreachable,
+    /// Requested PCLK2 is not HCLK divided by a valid APB2 prescaler
+    Pclk2Unreachable,
+    /// PLL x2 was selected for TIM1 or TIM8 but the PLL doesn't drive the
+    /// system clock, or AHB or APB2 is divided
+    TimClockUnreachable,
+    /// A USB clock was required but the PLL can't generate 48 or 72 MHz
+    /// from the HSE, or another system clock was requested
+    UsbClockUnreachable,
+    /// The clock security system was enabled without an HSE
+    CssWithoutHse,
+    /// The LSE was selected as a kernel clock but is not running
+    LseNotRunning,
+    /// The HSE didn't become ready in time, the system clock was left on the
+    /// HSI
+    HseTimeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MHZ: u32 = 1_000_000;
+
+    fn divider(prediv: u32) -> HseDivider {
+        match prediv {
+            1 => HseDivider::NoDivision,
+            2 => HseDivider::DivideBy2,
+            3 => HseDivider::DivideBy3,
+            4 => HseDivider::DivideBy4,
+            5 => HseDivider::DivideBy5,
+            6 => HseDivider::DivideBy6,
+            7 => HseDivider::DivideBy7,
+            8 => HseDivider::DivideBy8,
+            9 => HseDivider::DivideBy9,
+            10 => HseDivider::DivideBy10,
+            11 => HseDivider::DivideBy11,
+            12 => HseDivider::DivideBy12,
+            13 => HseDivider::DivideBy13,
+            14 => HseDivider::DivideBy14,
+            15 => HseDivider::DivideBy15,
+            16 => HseDivider::DivideBy16,
+            _ => unreachable!(),
+        }
+    }
+
+    fn bypass(enable: bool) -> HseBypass {
+        if enable {
+            HseBypass::Enable
+        } else {
+            HseBypass::Disable
+        }
+    }
+
+    fn hse(speed: u32, prediv: u32, bypassed: bool) -> CFGR {
+        CFGR::new().hse(Hertz(speed), divider(prediv), bypass(bypassed))
+    }
+
+    /// Brute force search, independent of `plan_pll`, of a PLL setting that
+    /// generates `sysclk` from the HSE or from HSI / 2
+    fn pll_reachable(hse: Option<u32>,
+                     prediv: Option<u32>,
+                     sysclk: u32)
+                     -> bool {
+        let src = hse.unwrap_or(HSI);
+        let predivs = match (hse, prediv) {
+            (None, _) => 2..=2,
+            (Some(_), Some(prediv)) => prediv..=prediv,
+            (Some(_), None) => 1..=16,
+        };
+        sysclk >= 16 * MHZ
+        && sysclk <= 72 * MHZ
+        && predivs.into_iter().any(|prediv| {
+                                   src >= MHZ * prediv
+                                   && src <= 24 * MHZ * prediv
+                                   && (2..=16).any(|pllmul| {
+                                                  src * pllmul
+                                                  == sysclk * prediv
+                                              })
+                               })
+    }
+
+    /// Checks the invariants of every plan
+    fn check_plan(plan: &ClockPlan, hse: Option<u32>) {
+        assert!(plan.sysclk <= 72 * MHZ, "{:?}", plan);
+        assert!(plan.pclk1 <= 36 * MHZ, "{:?}", plan);
+        assert!(plan.pclk2 <= 72 * MHZ, "{:?}", plan);
+        assert_eq!(plan.hclk * u32(plan.hpre()), plan.sysclk, "{:?}", plan);
+        assert_eq!(plan.pclk1 * u32(plan.ppre1), plan.hclk, "{:?}", plan);
+        assert_eq!(plan.pclk2 * u32(plan.ppre2), plan.hclk, "{:?}", plan);
+
+        match plan.source {
+            SysclkSource::Hsi => assert_eq!(plan.sysclk, HSI),
+            SysclkSource::Hse => assert_eq!(Some(plan.sysclk), hse),
+            SysclkSource::Pll { prediv, pllmul } => {
+                let (prediv, pllmul) = (u32(prediv), u32(pllmul));
+                let src = hse.unwrap_or(HSI);
+                if hse.is_none() {
+                    assert_eq!(prediv, 2);
+                }
+                assert!((1..=16).contains(&prediv), "{:?}", plan);
+                assert!((2..=16).contains(&pllmul), "{:?}", plan);
+                assert!(src >= MHZ * prediv && src <= 24 * MHZ * prediv,
+                        "{:?}",
+                        plan);
+                assert_eq!(src * pllmul, plan.sysclk * prediv, "{:?}", plan);
+            }
+        }
+
+        if plan.usb {
+            assert!(plan.sysclk == 48 * MHZ || plan.sysclk == 72 * MHZ);
+            assert!(hse.is_some());
+            assert_eq!(plan.usbclk().map(|f| f.0), Some(USB));
+        }
+    }
+
+    #[test]
+    fn sysclk_from_hse() {
+        for speed in (4..=32).map(|mhz| mhz * MHZ) {
+            for prediv in 1..=16 {
+                for &bypassed in &[false, true] {
+                    for sysclk in (1..=160).map(|n| n * MHZ / 2) {
+                        let result = hse(speed, prediv, bypassed)
+                            .sysclk(Hertz(sysclk))
+                            .plan();
+
+                        let expected = if sysclk == speed {
+                            Ok(())
+                        } else if sysclk > 72 * MHZ {
+                            Err(ClockError::SysclkTooHigh)
+                        } else if pll_reachable(Some(speed), None, sysclk) {
+                            Ok(())
+                        } else {
+                            Err(ClockError::SysclkUnreachable)
+                        };
+                        assert_eq!(result.map(|_| ()),
+                                   expected,
+                                   "HSE {} / {}, SYSCLK {}",
+                                   speed,
+                                   prediv,
+                                   sysclk);
+
+                        if let Ok(plan) = result {
+                            check_plan(&plan, Some(speed));
+                            assert_eq!(plan.sysclk, sysclk);
+                            assert_eq!(plan.hse, Some(bypassed));
+                            // the requested divider is preferred
+                            if sysclk != speed
+                               && pll_reachable(Some(speed),
+                                                Some(prediv),
+                                                sysclk)
+                            {
+                                match plan.source {
+                                    SysclkSource::Pll { prediv: p, .. } => {
+                                        assert_eq!(u32(p), prediv)
+                                    }
+                                    source => panic!("{:?}", source),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sysclk_from_hsi() {
+        for sysclk in (1..=160).map(|n| n * MHZ / 2) {
+            let result = CFGR::new().sysclk(Hertz(sysclk)).plan();
+
+            let expected = if sysclk == HSI {
+                Ok(())
+            } else if sysclk > 72 * MHZ {
+                Err(ClockError::SysclkTooHigh)
+            } else if pll_reachable(None, None, sysclk) {
+                Ok(())
+            } else {
+                Err(ClockError::SysclkUnreachable)
+            };
+            assert_eq!(result.map(|_| ()), expected, "SYSCLK {}", sysclk);
+
+            if let Ok(plan) = result {
+                check_plan(&plan, None);
+                assert_eq!(plan.sysclk, sysclk);
+                assert_eq!(plan.hse, None);
+            }
+        }
+    }
+
+    #[test]
+    fn default_clocks() {
+        let plan = CFGR::new().plan().unwrap();
+        check_plan(&plan, None);
+        assert_eq!(plan.source, SysclkSource::Hsi);
+        assert_eq!((plan.hclk, plan.pclk1, plan.pclk2), (HSI, HSI, HSI));
+
+        for speed in (4..=32).map(|mhz| mhz * MHZ) {
+            let plan = hse(speed, 1, false).plan().unwrap();
+            check_plan(&plan, Some(speed));
+            assert_eq!(plan.source, SysclkSource::Hse);
+            assert_eq!(plan.sysclk, speed);
+        }
+    }
+
+    #[test]
+    fn hse_range() {
+        let cases = [(999_999, true, false),
+                     (MHZ, true, true),
+                     (3_999_999, false, false),
+                     (4 * MHZ, false, true),
+                     (32 * MHZ, false, true),
+                     (32 * MHZ, true, true),
+                     (32_000_001, false, false),
+                     (32_000_001, true, false)];
+        for &(speed, bypassed, valid) in &cases {
+            let result = hse(speed, 1, bypassed).plan().map(|_| ());
+            let expected = if valid {
+                Ok(())
+            } else {
+                Err(ClockError::HseOutOfRange)
+            };
+            assert_eq!(result, expected, "HSE {} bypass {}", speed, bypassed);
+        }
+    }
+
+    #[test]
+    fn bus_clocks() {
+        let sysclks = [8, 16, 24, 32, 36, 48, 56, 64, 72];
+        for sysclk in sysclks.iter().map(|mhz| mhz * MHZ) {
+            let base = hse(8 * MHZ, 1, false).sysclk(Hertz(sysclk));
+
+            for hdiv in 1..=512 {
+                if sysclk % hdiv != 0 {
+                    continue;
+                }
+                let hclk = sysclk / hdiv;
+                let cfgr = base.clone().hclk(Hertz(hclk));
+                let valid = HPRE.iter().any(|&(div, _)| div == hdiv);
+                let result = cfgr.plan();
+                if !valid {
+                    assert_eq!(result, Err(ClockError::HclkUnreachable));
+                    continue;
+                }
+
+                // unconstrained APB clocks run as fast as allowed
+                let plan = result.unwrap();
+                check_plan(&plan, Some(8 * MHZ));
+                assert_eq!(plan.hclk, hclk);
+                assert_eq!(plan.pclk2, hclk);
+                let fastest = PPRE.iter()
+                                  .map(|&(div, _)| hclk / div)
+                                  .find(|&pclk| pclk <= 36 * MHZ)
+                                  .unwrap();
+                assert_eq!(plan.pclk1, fastest);
+
+                for pdiv in 1..=32 {
+                    if hclk % pdiv != 0 {
+                        continue;
+                    }
+                    let pclk = hclk / pdiv;
+                    let prescaler = PPRE.iter().any(|&(div, _)| div == pdiv);
+
+                    let result = cfgr.clone().pclk1(Hertz(pclk)).plan();
+                    if prescaler && pclk <= 36 * MHZ {
+                        let plan = result.unwrap();
+                        check_plan(&plan, Some(8 * MHZ));
+                        assert_eq!(plan.pclk1, pclk);
+                    } else {
+                        assert_eq!(result, Err(ClockError::Pclk1Unreachable));
+                    }
+
+                    let result = cfgr.clone().pclk2(Hertz(pclk)).plan();
+                    if prescaler {
+                        let plan = result.unwrap();
+                        check_plan(&plan, Some(8 * MHZ));
+                        assert_eq!(plan.pclk2, pclk);
+                    } else {
+                        assert_eq!(result, Err(ClockError::Pclk2Unreachable));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn usb_clock() {
+        assert_eq!(CFGR::new().require_usb_clock().plan(),
+                   Err(ClockError::UsbClockUnreachable));
+
+        for speed in (4..=32).map(|mhz| mhz * MHZ) {
+            for prediv in 1..=16 {
+                let base = hse(speed, prediv, false).require_usb_clock();
+
+                let reachable =
+                    |sysclk| pll_reachable(Some(speed), None, sysclk);
+                let requests = [(None, &[72 * MHZ, 48 * MHZ][..]),
+                                (Some(72 * MHZ), &[72 * MHZ][..]),
+                                (Some(48 * MHZ), &[48 * MHZ][..]),
+                                (Some(64 * MHZ), &[][..])];
+                for &(sysclk, targets) in &requests {
+                    let cfgr = match sysclk {
+                        Some(sysclk) => base.clone().sysclk(Hertz(sysclk)),
+                        None => base.clone(),
+                    };
+                    let result = cfgr.plan();
+                    match targets.iter().find(|&&t| reachable(t)) {
+                        Some(&target) => {
+                            let plan = result.unwrap();
+                            check_plan(&plan, Some(speed));
+                            assert!(plan.usb);
+                            assert_eq!(plan.sysclk, target);
+                        }
+                        None => {
+                            assert_eq!(result,
+                                       Err(ClockError::UsbClockUnreachable))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_clocks() {
+        assert_eq!(CFGR::new().enable_css().plan(),
+                   Err(ClockError::CssWithoutHse));
+        assert!(hse(8 * MHZ, 1, false).enable_css().plan().unwrap().css);
+
+        let mut cfgr = CFGR::new();
+        cfgr.kernel.usart[1] = UsartClock::Lse;
+        assert_eq!(cfgr.clone().plan(), Err(ClockError::LseNotRunning));
+        cfgr.lse = true;
+        let plan = cfgr.plan().unwrap();
+        assert_eq!(plan.usartclk(1).0, LSE);
+
+        // PLL x2 needs the PLL with undivided AHB and APB2
+        let tim = CFGR::new().tim1_clock(TimClock::Pll);
+        assert_eq!(tim.clone().plan(), Err(ClockError::TimClockUnreachable));
+        assert_eq!(tim.clone().sysclk(Hertz(64 * MHZ))
+                      .hclk(Hertz(32 * MHZ))
+                      .plan(),
+                   Err(ClockError::TimClockUnreachable));
+        assert_eq!(tim.clone().sysclk(Hertz(64 * MHZ))
+                      .pclk2(Hertz(32 * MHZ))
+                      .plan(),
+                   Err(ClockError::TimClockUnreachable));
+        let plan = tim.sysclk(Hertz(64 * MHZ)).plan().unwrap();
+        check_plan(&plan, None);
+        assert_eq!(plan.timclk(TimClock::Pll).0, 128 * MHZ);
+    }
+}
@@ -6,17 +6,12 @@ use core::sync::atomic::{self, Ordering};
 
 use hal::serial::{self, Write};
 use nb;
-use crate::pac::{Interrupt, RCC, USART1, USART2, USART3};
+use crate::pac::{Interrupt, USART1, USART2, USART3};
 use void::Void;
 
 use crate::dma::{dma1, CircBuffer, Static, Transfer, R, W};
-use crate::gpio::{AltFn, HighSpeed, PinMode, PullType, PushPull, AF7};
-use crate::gpio::{PA10, PA14, PA15, PA2, PA3, PA9};
-use crate::gpio::{PB10, PB11, PB3, PB4, PB6, PB7};
-use crate::gpio::{PC10, PC11, PC4, PC5};
-use crate::gpio::{PD5, PD6, PD8, PD9};
-use crate::gpio::{PE0, PE1, PE15};
-use crate::rcc::Clocks;
+use crate::gpio::{AltFnMode, AltFnNum, PortPin};
+use crate::rcc::{Clocks, Enable, RccBus, Reset};
 use crate::time::Bps;
 
 /// Interrupt event
@@ -46,6 +41,7 @@ pub enum Error {
 pub struct Serial<USART, PINS> {
     usart: USART,
     pins: PINS,
+    baud_rate: Bps<u32>,
 }
 
 /// Serial receiver
@@ -58,116 +54,98 @@ pub struct Tx<USART> {
     _usart: PhantomData<USART>,
 }
 
+/// TX pin of `USART`
+///
+/// Implemented for every pin that can be routed to `USART` on the selected
+/// device and package
+pub trait PinTx<USART> {
+    /// Alternate function that connects the pin to `USART`
+    type AF: AltFnNum + Default;
+}
+
+/// RX pin of `USART`
+pub trait PinRx<USART> {
+    /// Alternate function that connects the pin to `USART`
+    type AF: AltFnNum + Default;
+}
+
 /// Serial extension for USART
-pub trait SerialExt<USART, ITX, IRX, TX, RX> {
+pub trait SerialExt<USART: RccBus, TX, RX> {
     /// Configures USART and consumes pair of (tx, rx) pins
     /// to act as serial port.
-    /// Configures pins accordingly.
+    /// Pins must already be switched to the matching alternate function;
+    /// their pull, output type and speed are left as configured.
     /// Returns [`Serial`].
     ///
     /// [`Serial`]: ./struct.Serial.html
     fn serial(self,
-              pins: (ITX, IRX),
+              pins: (TX, RX),
               baud_rate: Bps<u32>,
-              clocks: Clocks)
+              clocks: Clocks,
+              apb: &mut USART::Bus)
               -> Serial<USART, (TX, RX)>;
 }
 
 macro_rules! serial {
     ($USARTX:ident,
      $INTNAME:ident,
-     $apbenr:ident,
-     $apbrstr:ident,
-     $usartXen:ident,
-     $usartXrst:ident,
-     $pclkX:ident,
-     $afn:ident,
-     $speed:ident,
-     [$($txpin: ident, )+],
-     $restrx: tt
-    ) => {
-        serial!{$USARTX,
-                $INTNAME,
-                $apbenr,
-                $apbrstr,
-                $usartXen,
-                $usartXrst,
-                $pclkX,
-                $afn,
-                $speed,
-                [$(
-                    ($txpin, $restrx),
-                )+]}
-    };
-    ($USARTX:ident,
-     $INTNAME:ident,
-     $apbenr:ident,
-     $apbrstr:ident,
-     $usartXen:ident,
-     $usartXrst:ident,
-     $pclkX:ident,
-     $afn:ident,
-     $speed:ident,
-     [$(($txpin: ident, [$($rxpin: ident,)+]), )+]
+     $usartXclk:ident
     ) => {
-        $(
-            $(
-                impl <PT: PullType,
-                PM: PinMode>
-                    SerialExt<$USARTX,
-                $txpin<PT, PM>,
-                $rxpin<PT, PM>,
-                $txpin<PT, AltFn<$afn, PushPull, $speed>>,
-                $rxpin<PT, AltFn<$afn, PushPull, $speed>>>
-                    for $USARTX {
-                        fn serial(self,
-                                  pins: ($txpin<PT, PM>, $rxpin<PT, PM>),
-                                  baud_rate: Bps<u32>,
-                                  clocks: Clocks)
-                                  -> Serial<$USARTX, ($txpin<PT, AltFn<$afn, PushPull, $speed>>,
-                                                      $rxpin<PT, AltFn<$afn, PushPull, $speed>>)>
-                        {
-                            let outpins = (
-                                pins.0
-                                    .alternating($afn)
-                                    .output_speed($speed),
-                                pins.1
-                                    .alternating($afn)
-                                    .output_speed($speed),
-                            );
-
-                            // enable or reset $USARTX
-                            let apbenr = unsafe { &(*RCC::ptr()).$apbenr };
-                            let apbrstr = unsafe { &(*RCC::ptr()).$apbrstr };
-                            apbenr.modify(|_, w| w.$usartXen().enabled());
-                            apbrstr.modify(|_, w| w.$usartXrst().set_bit());
-                            apbrstr.modify(|_, w| w.$usartXrst().clear_bit());
-                            self.cr3.write(|w| w.dmat().set_bit().dmar().set_bit());
-
-                            let brr = clocks.$pclkX().0 / baud_rate.0;
-                            assert!(brr >= 16, "impossible baud rate");
-                            self.brr.write(|w| unsafe { w.bits(brr) });
-
-                            // UE: enable USART
-                            // RE: enable receiver
-                            // TE: enable transceiver
-                            self.cr1.write(|w| {
-                                w.ue()
-                                    .set_bit()
-                                    .re()
-                                    .set_bit()
-                                    .te()
-                                    .set_bit()
-                            });
-
-                            Serial { usart: self,
-                                     pins: outpins, }
-                        }
-                    }
-            )+
-        )+
+        impl<TX, RX> SerialExt<$USARTX, TX, RX> for $USARTX
+            where TX: PinTx<$USARTX> + PortPin,
+                  TX::Mode: AltFnMode<AF = <TX as PinTx<$USARTX>>::AF>,
+                  RX: PinRx<$USARTX> + PortPin,
+                  RX::Mode: AltFnMode<AF = <RX as PinRx<$USARTX>>::AF>
+        {
+            fn serial(self,
+                      pins: (TX, RX),
+                      baud_rate: Bps<u32>,
+                      clocks: Clocks,
+                      apb: &mut <$USARTX as RccBus>::Bus)
+                      -> Serial<$USARTX, (TX, RX)>
+            {
+                // enable or reset $USARTX
+                $USARTX::enable(apb);
+                $USARTX::reset(apb);
+                self.cr3.write(|w| w.dmat().set_bit().dmar().set_bit());
+
+                let brr = clocks.$usartXclk().0 / baud_rate.0;
+                assert!(brr >= 16, "impossible baud rate");
+                self.brr.write(|w| unsafe { w.bits(brr) });
+
+                // UE: enable USART
+                // RE: enable receiver
+                // TE: enable transceiver
+                self.cr1.write(|w| {
+                    w.ue()
+                        .set_bit()
+                        .re()
+                        .set_bit()
+                        .te()
+                        .set_bit()
+                });
+
+                Serial { usart: self, pins, baud_rate }
+            }
+        }
 
         impl<TX, RX> Serial<$USARTX, (TX, RX)> {
+            /// Recomputes the baud rate divider after the clock tree was
+            /// reconfigured
+            ///
+            /// Waits for the ongoing transmission to complete, a frame being
+            /// received meanwhile is lost.
+            pub fn reclock(&mut self, clocks: &Clocks) {
+                let brr = clocks.$usartXclk().0 / self.baud_rate.0;
+                assert!(brr >= 16, "impossible baud rate");
+
+                while self.usart.isr.read().tc().bit_is_clear() {}
+                // BRR can only be written while the USART is disabled
+                self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                self.usart.brr.write(|w| unsafe { w.bits(brr) });
+                self.usart.cr1.modify(|_, w| w.ue().set_bit());
+            }
+
             /// Returns associated interrupt
             pub fn get_interrupt(&self) -> Interrupt {
                 Interrupt::$INTNAME
@@ -204,7 +182,12 @@ macro_rules! serial {
             }
 
             /// Releases the USART peripheral and associated pins
-            pub fn free(self) -> ($USARTX, (TX, RX)) {
+            ///
+            /// The USART clock is gated off.
+            pub fn free(self,
+                        apb: &mut <$USARTX as RccBus>::Bus)
+                        -> ($USARTX, (TX, RX)) {
+                $USARTX::disable(apb);
                 (self.usart, self.pins)
             }
         }
@@ -531,36 +514,6 @@ pub trait WriteDma<A, B>: DmaChannel
 }
 
 // XXX: we can't use GATs yet, so had to retort to macros
-serial!(USART1,
-        USART1_EXTI25,
-        apb2enr,
-        apb2rstr,
-        usart1en,
-        usart1rst,
-        pclk2,
-        AF7,
-        HighSpeed, // XXX: not sure, maybe we should allow setting this
-        [PA9, PB6, PC4, PE0,],
-        [PA10, PB7, PC5, PE1,]);
-serial!(USART2,
-        USART2_EXTI26,
-        apb1enr,
-        apb1rstr,
-        usart2en,
-        usart2rst,
-        pclk1,
-        AF7,
-        HighSpeed,
-        [PA2, PA14, PB3, PD5,],
-        [PA3, PA15, PB4, PD6,]);
-serial!(USART3,
-        USART3_EXTI28,
-        apb1enr,
-        apb1rstr,
-        usart3en,
-        usart3rst,
-        pclk1,
-        AF7,
-        HighSpeed,
-        [PB10, PC10, PD8,],
-        [PB11, PC11, PD9, PE15,]);
+serial!(USART1, USART1_EXTI25, usart1clk);
+serial!(USART2, USART2_EXTI26, usart2clk);
+serial!(USART3, USART3_EXTI28, usart3clk);
@@ -2,16 +2,12 @@
 
 use core::ptr;
 
-use crate::pac::{RCC, SPI1, SPI2, SPI3};
+use crate::pac::{SPI1, SPI2, SPI3};
 use hal::spi::{FullDuplex, Mode, Phase, Polarity};
 use nb;
 
-use crate::gpio::{AltFn, PullType, AF5, AF6};
-use crate::gpio::{HighSpeed, PinMode, PushPull};
-use crate::gpio::{PA5, PA6, PA7};
-use crate::gpio::{PB13, PB14, PB15, PB3, PB4, PB5};
-use crate::gpio::{PC10, PC11, PC12};
-use crate::rcc::Clocks;
+use crate::gpio::{AltFnMode, AltFnNum, PortPin};
+use crate::rcc::{BusClock, Clocks, Enable, RccBus, Reset};
 use crate::time::Hertz;
 
 /// SPI error
@@ -31,199 +27,165 @@ pub enum Error {
 pub struct Spi<SPI, PINS> {
     spi: SPI,
     pins: PINS,
+    freq: Hertz<u32>,
+}
+
+/// SCK pin of `SPI`
+///
+/// Implemented for every pin that can be routed to `SPI` on the selected
+/// device and package
+pub trait PinSck<SPI> {
+    /// Alternate function that connects the pin to `SPI`
+    type AF: AltFnNum + Default;
+}
+
+/// MISO pin of `SPI`
+pub trait PinMiso<SPI> {
+    /// Alternate function that connects the pin to `SPI`
+    type AF: AltFnNum + Default;
+}
+
+/// MOSI pin of `SPI`
+pub trait PinMosi<SPI> {
+    /// Alternate function that connects the pin to `SPI`
+    type AF: AltFnNum + Default;
 }
 
 /// SPI extension for SPI
-pub trait SpiExt<SPI, ISCK, IMISO, IMOSI, SCK, MISO, MOSI> {
+pub trait SpiExt<SPI: RccBus, SCK, MISO, MOSI> {
     /// Configures the SPI peripheral to operate in full duplex master mode.
     /// Consumes SPI peripheral and triple of (SCK, MISO, MOSI) pins.
+    /// Pins must already be switched to the matching alternate function;
+    /// their pull, output type and speed are left as configured.
     /// Returns [`Spi`].
     ///
     /// [`Spi`]: ./struct.Spi.html
     fn spi<F>(self,
-              pins: (ISCK, IMISO, IMOSI),
+              pins: (SCK, MISO, MOSI),
               mode: Mode,
               freq: F,
-              clocks: Clocks)
+              clocks: Clocks,
+              apb: &mut SPI::Bus)
               -> Spi<SPI, (SCK, MISO, MOSI)>
         where F: Into<Hertz<u32>>;
 }
 
+/// Returns the BR bits of the divider closest to `pclk / freq`
+fn baud_rate_prescaler(pclk: Hertz<u32>, freq: Hertz<u32>) -> u8 {
+    match pclk.0 / freq.0 {
+        0 => unreachable!(),
+        1..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=39 => 0b100,
+        40..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 macro_rules! spi {
-    ($SPIX:ident,
-     $apbenr:ident,
-     $apbrstr:ident,
-     $spiXen:ident,
-     $spiXrst:ident,
-     $pclkX:ident,
-     $afn:ident,
-     $speed:ident,
-     sck: [$($sck: ident, )+],
-     miso: $miso: tt,
-     mosi: $mosi: tt
-    ) => {
-        spi!{
-            $SPIX,
-            $apbenr,
-            $apbrstr,
-            $spiXen,
-            $spiXrst,
-            $pclkX,
-            $afn,
-            $speed,
-            [$(
-                ($sck, $miso, ),
-            )+],
-            $mosi
-        }
-    };
-    ($SPIX:ident,
-     $apbenr:ident,
-     $apbrstr:ident,
-     $spiXen:ident,
-     $spiXrst:ident,
-     $pclkX:ident,
-     $afn:ident,
-     $speed:ident,
-     [$(($sck: ident,
-         [$($miso: ident, )+], ),
-     )+],
-     $mosi: tt
-    ) => {
-        spi!{
-            $SPIX,
-            $apbenr,
-            $apbrstr,
-            $spiXen,
-            $spiXrst,
-            $pclkX,
-            $afn,
-            $speed,
-            [$(
-                ($sck,
-                 [$(
-                     ($miso, $mosi),
-                 )+]),
-            )+]
+    ($SPIX:ident) => {
+        impl<SCK, MISO, MOSI> SpiExt<$SPIX, SCK, MISO, MOSI> for $SPIX
+            where SCK: PinSck<$SPIX> + PortPin,
+                  SCK::Mode: AltFnMode<AF = <SCK as PinSck<$SPIX>>::AF>,
+                  MISO: PinMiso<$SPIX> + PortPin,
+                  MISO::Mode: AltFnMode<AF = <MISO as PinMiso<$SPIX>>::AF>,
+                  MOSI: PinMosi<$SPIX> + PortPin,
+                  MOSI::Mode: AltFnMode<AF = <MOSI as PinMosi<$SPIX>>::AF>
+        {
+            fn spi<F>(
+                self,
+                pins: (SCK, MISO, MOSI),
+                mode: Mode,
+                freq: F,
+                clocks: Clocks,
+                apb: &mut <$SPIX as RccBus>::Bus)
+                -> Spi<$SPIX, (SCK, MISO, MOSI)>
+            where F: Into<Hertz<u32>>
+            {
+                // enable or reset $SPIX
+                $SPIX::enable(apb);
+                $SPIX::reset(apb);
+
+                // FRXTH: RXNE event is generated if the FIFO level is greater
+                // than or equal to        8-bit
+                // DS: 8-bit data size
+                // SSOE: Slave Select output disabled
+                self.cr2.write(|w| unsafe {
+                    w.frxth()
+                        .set_bit()
+                        .ds()
+                        .bits(0b111)
+                        .ssoe()
+                        .clear_bit()
+                });
+
+                let freq = freq.into();
+                let br = baud_rate_prescaler($SPIX::clock(&clocks), freq);
+
+                // CPHA: phase
+                // CPOL: polarity
+                // MSTR: master mode
+                // BR: 1 MHz
+                // SPE: SPI disabled
+                // LSBFIRST: MSB first
+                // SSM: enable software slave management (NSS pin free for
+                // other uses) SSI: set nss high = master mode
+                // CRCEN: hardware CRC calculation disabled
+                // BIDIMODE: 2 line unidirectional (full duplex)
+                self.cr1.write(|w|
+                    w.cpha()
+                        .bit(mode.phase
+                             == Phase::CaptureOnSecondTransition)
+                        .cpol()
+                        .bit(mode.polarity == Polarity::IdleHigh)
+                        .mstr()
+                        .set_bit()
+                        .br()
+                        .bits(br)
+                        .spe()
+                        .set_bit()
+                        .lsbfirst()
+                        .clear_bit()
+                        .ssi()
+                        .set_bit()
+                        .ssm()
+                        .set_bit()
+                        .crcen()
+                        .clear_bit()
+                        .bidimode()
+                        .clear_bit()
+                );
+
+                Spi { spi: self, pins, freq }
+            }
         }
-    };
-    ($SPIX:ident,
-     $apbenr:ident,
-     $apbrstr:ident,
-     $spiXen:ident,
-     $spiXrst:ident,
-     $pclkX:ident,
-     $afn:ident,
-     $speed:ident,
-     [$(($sck: ident,
-         [$(($miso: ident, [$($mosi: ident,)+]), )+]
-     ), )+]
-    ) => {
-        $(
-            $(
-                $(
-                    impl<PT: PullType, PM: PinMode>
-                        SpiExt<$SPIX,
-                    $sck<PT, PM>,
-                    $miso<PT, PM>,
-                    $mosi<PT, PM>,
-                    $sck<PT, AltFn<$afn, PushPull, $speed>>,
-                    $miso<PT, AltFn<$afn, PushPull, $speed>>,
-                    $mosi<PT, AltFn<$afn, PushPull, $speed>>> for $SPIX
-                    {
-                        fn spi<F>(
-                            self,
-                            pins: ($sck<PT, PM>, $miso<PT, PM>, $mosi<PT, PM>),
-                            mode: Mode,
-                            freq: F,
-                            clocks: Clocks)
-                            -> Spi<$SPIX,
-                        ($sck<PT, AltFn<$afn, PushPull, $speed>>,
-                         $miso<PT, AltFn<$afn, PushPull, $speed>>,
-                         $mosi<PT, AltFn<$afn, PushPull, $speed>>)>
-                        where F: Into<Hertz<u32>>
-                        {
-                            let outpins = (pins.0.alternating($afn).output_speed($speed),
-                                           pins.1.alternating($afn).output_speed($speed),
-                                           pins.2.alternating($afn).output_speed($speed));
-                            let apbenr = unsafe { &(*RCC::ptr()).$apbenr };
-                            let apbrstr = unsafe { &(*RCC::ptr()).$apbrstr };
-                            // enable or reset $SPIX
-                            apbenr.modify(|_, w| w.$spiXen().enabled());
-                            apbrstr.modify(|_, w| w.$spiXrst().set_bit());
-                            apbrstr.modify(|_, w| w.$spiXrst().clear_bit());
-
-                            // FRXTH: RXNE event is generated if the FIFO level is greater
-                            // than or equal to        8-bit
-                            // DS: 8-bit data size
-                            // SSOE: Slave Select output disabled
-                            self.cr2.write(|w| unsafe {
-                                w.frxth()
-                                    .set_bit()
-                                    .ds()
-                                    .bits(0b111)
-                                    .ssoe()
-                                    .clear_bit()
-                            });
-
-                            let br = match clocks.$pclkX().0 / freq.into().0 {
-                                0 => unreachable!(),
-                                1..=2 => 0b000,
-                                3..=5 => 0b001,
-                                6..=11 => 0b010,
-                                12..=23 => 0b011,
-                                24..=39 => 0b100,
-                                40..=95 => 0b101,
-                                96..=191 => 0b110,
-                                _ => 0b111,
-                            };
-
-                            // CPHA: phase
-                            // CPOL: polarity
-                            // MSTR: master mode
-                            // BR: 1 MHz
-                            // SPE: SPI disabled
-                            // LSBFIRST: MSB first
-                            // SSM: enable software slave management (NSS pin free for
-                            // other uses) SSI: set nss high = master mode
-                            // CRCEN: hardware CRC calculation disabled
-                            // BIDIMODE: 2 line unidirectional (full duplex)
-                            self.cr1.write(|w|
-                                w.cpha()
-                                    .bit(mode.phase
-                                         == Phase::CaptureOnSecondTransition)
-                                    .cpol()
-                                    .bit(mode.polarity == Polarity::IdleHigh)
-                                    .mstr()
-                                    .set_bit()
-                                    .br()
-                                    .bits(br)
-                                    .spe()
-                                    .set_bit()
-                                    .lsbfirst()
-                                    .clear_bit()
-                                    .ssi()
-                                    .set_bit()
-                                    .ssm()
-                                    .set_bit()
-                                    .crcen()
-                                    .clear_bit()
-                                    .bidimode()
-                                    .clear_bit()
-                            );
-
-                            Spi { spi: self,
-                                  pins: outpins, }
-                        }
-                    }
-                )+
-            )+
-        )+
 
         impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+            /// Recomputes the baud rate prescaler after the clock tree was
+            /// reconfigured
+            ///
+            /// Waits for the ongoing transfer to complete.
+            pub fn reclock(&mut self, clocks: &Clocks) {
+                let br = baud_rate_prescaler($SPIX::clock(clocks), self.freq);
+
+                while self.spi.sr.read().bsy().bit_is_set() {}
+                // BR can only be written while the SPI is disabled
+                self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                self.spi.cr1.modify(|_, w| w.br().bits(br));
+                self.spi.cr1.modify(|_, w| w.spe().set_bit());
+            }
+
             /// Releases the SPI peripheral and associated pins
-            pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI)) {
+            ///
+            /// The SPI clock is gated off.
+            pub fn free(self,
+                        apb: &mut <$SPIX as RccBus>::Bus)
+                        -> ($SPIX, (SCK, MISO, MOSI)) {
+                $SPIX::disable(apb);
                 (self.spi, self.pins)
             }
         }
@@ -284,39 +246,6 @@ macro_rules! spi {
     };
 }
 
-//// DEFINE traits for Spi1Sck, Spi1Miso or, even better Sck<dev = SPI1>
-
-spi!(SPI1,
-     apb2enr,
-     apb2rstr,
-     spi1en,
-     spi1rst,
-     pclk2,
-     AF5,
-     HighSpeed,
-     sck: [PA5, PB3,],
-     miso: [PA6, PB4,],
-     mosi: [PA7, PB5,]);
-
-spi!(SPI2,
-     apb1enr,
-     apb1rstr,
-     spi2en,
-     spi2rst,
-     pclk1,
-     AF5,
-     HighSpeed,
-     sck: [PB13,],
-     miso: [PB14,],
-     mosi: [PB15,]);
-spi!(SPI3,
-     apb1enr,
-     apb1rstr,
-     spi3en,
-     spi3rst,
-     pclk1,
-     AF6,
-     HighSpeed,
-     sck: [PB3, PC10,],
-     miso: [PB4, PC11,],
-     mosi: [PB5, PC12,]);
+spi!(SPI1);
+spi!(SPI2);
+spi!(SPI3);
@@ -1,7 +1,7 @@
 //! SYSCFG Clock Configuration
 
 use crate::pac::{self, SYSCFG};
-use crate::rcc;
+use crate::rcc::{self, Enable};
 
 /// Extension trait that constrains the `SYSCFG` peripheral
 pub trait SyscfgExt {
@@ -12,7 +12,7 @@ pub trait SyscfgExt {
 
 impl SyscfgExt for SYSCFG {
     fn constrain(self, apb2: &mut rcc::APB2) -> Syscfg {
-        apb2.enr().modify(|_, w| w.syscfgen().enabled());
+        SYSCFG::enable(apb2);
 
         Syscfg { _0: () }
     }
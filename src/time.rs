@@ -28,6 +28,14 @@ impl MonoTimer {
         MonoTimer { frequency: clocks.sysclk() }
     }
 
+    /// Updates the timer frequency after the clock tree was reconfigured
+    ///
+    /// Durations between instants taken before and after the change are
+    /// meaningless.
+    pub fn reclock(&mut self, clocks: &Clocks) {
+        self.frequency = clocks.sysclk();
+    }
+
     /// Returns the frequency at which the monotonic timer is operating at
     pub fn frequency(&self) -> Hertz<u32> {
         self.frequency
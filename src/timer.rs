@@ -2,6 +2,7 @@
 
 use bobbin_bits::*;
 use cast::{u16, u32};
+use core::cmp;
 use core::intrinsics::transmute;
 use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m::peripheral::SYST;
@@ -79,6 +80,13 @@ pub mod syst {
             self.tim.clear_current();
         }
 
+        /// Recomputes the reload value after the clock tree was reconfigured
+        pub fn reclock(&mut self, clocks: &Clocks) {
+            self.clocks = *clocks;
+            let timeout = self.timeout;
+            self.reset(timeout);
+        }
+
         /// Starts listening for an `event`
         pub fn listen(&mut self, event: Event) {
             match event {
@@ -262,13 +270,129 @@ impl ChNum for CH4 {
     }
 }
 
+/// Returns the prescaler and auto-reload values that make a timer clocked at
+/// `timclk` overflow at `frequency`
+///
+/// Frequencies above half the timer clock are clamped to it, the fastest
+/// rate a running counter can overflow at.
+fn psc_arr(timclk: u32, frequency: u32) -> (u16, u16) {
+    // the counter is blocked while the auto-reload value is 0
+    let ticks = cmp::max(timclk / frequency, 2);
+    let psc = (ticks - 1) / (1 << 16);
+    let arr = ticks / (psc + 1) - 1;
+    // NOTE(unwrap) ticks < 2^32, so both fit in 16 bits
+    (u16(psc).unwrap(), u16(arr).unwrap())
+}
+
+/// Timeout handling and `CountDown` implementation shared by the TIM timers
+macro_rules! count_down {
+    (impl<$($P:ident: $B:ident),*> $Timer:ty) => {
+        impl<$($P: $B),*> $Timer {
+            /// Stop timer and reset frequency (doesn't start/enable)
+            pub fn reset<T>(&mut self, timeout: T)
+                where T: Into<Hertz<u32>>
+            {
+                self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                let timeout = timeout.into();
+                let mult = if self.clocks.ppre1() == 1 { 1 } else { 2 };
+                let timclk = self.clocks.pclk1().0 * mult;
+                let (psc, arr) = psc_arr(timclk, timeout.0);
+                self.tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
+                self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
+                // load the prescaler and restart the counter
+                self.tim.egr.write(|w| w.ug().set_bit());
+                self.timeout = timeout;
+            }
+
+            /// Recomputes the prescaler and auto-reload values after the
+            /// clock tree was reconfigured
+            ///
+            /// The counter restarts from 0 and keeps running if it was
+            /// enabled. PWM duty cycles are relative to the auto-reload value
+            /// and have to be set again.
+            pub fn reclock(&mut self, clocks: &Clocks) {
+                let enabled = self.tim.cr1.read().cen().bit_is_set();
+                self.clocks = *clocks;
+                let timeout = self.timeout;
+                self.reset(timeout);
+                if enabled {
+                    self.enable();
+                }
+            }
+
+            /// Starts listening for an `event`
+            pub fn listen(&mut self, event: Event) {
+                match event {
+                    Event::TimeOut => {
+                        // Enable update event interrupt
+                        self.tim.dier.modify(|_, w| w.uie().set_bit());
+                    }
+                }
+            }
+
+            /// Stops listening for an `event`
+            pub fn unlisten(&mut self, event: Event) {
+                match event {
+                    Event::TimeOut => {
+                        // Disable update event interrupt
+                        self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                    }
+                }
+            }
+
+            /// Clears the interrupt flag of an `event`
+            pub fn clear_interrupt(&mut self, event: Event) {
+                match event {
+                    Event::TimeOut => {
+                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    }
+                }
+            }
+
+            /// Returns `true` if the counter overflowed since the update flag
+            /// was last cleared, by `wait` or `clear_interrupt`
+            pub fn is_update_pending(&self) -> bool {
+                self.tim.sr.read().uif().bit_is_set()
+            }
+
+            /// Enable timer
+            pub fn enable(&mut self) {
+                // enable counter
+                self.tim.cr1.modify(|_, w| w.cen().bit(true));
+            }
+        }
+
+        impl<$($P: $B),*> Periodic for $Timer {}
+
+        impl<$($P: $B),*> CountDown for $Timer {
+            type Time = Hertz<u32>;
+
+            fn start<T>(&mut self, timeout: T)
+                where T: Into<Hertz<u32>>
+            {
+                self.reset(timeout);
+                self.enable();
+            }
+
+            fn wait(&mut self) -> nb::Result<(), Void> {
+                if self.is_update_pending() {
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+    };
+}
+
 macro_rules! tim {
-    ($TIMSRC:ident, $apb:ident, $timmod:ident, $timXen:ident, $timXrst:ident) => {
+    ($TIMSRC:ident, $apb:ident, $timmod:ident) => {
         /// $TIMSRC impl
         pub mod $timmod {
             use super::*;
             use crate::rcc;
-            use crate::rcc::Clocks;
+            use crate::rcc::{Clocks, Enable, Reset};
             use core::marker::PhantomData;
 
             /// Timer channel
@@ -397,16 +521,17 @@ macro_rules! tim {
                 /// Creates new timer
                 pub fn new<T>(tim: $TIMSRC,
                               timeout: T,
-                              clocks: Clocks)
+                              clocks: Clocks,
+                              apb: &mut rcc::$apb)
                               -> Timer<PwmFree>
                     where T: Into<Hertz<u32>>
                 {
-                    let mut apb = rcc::$apb::internal_get();
                     // enable and reset peripheral to a clean slate state
-                    apb.enr().modify(|_, w| w.$timXen().enabled());
-                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
-                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+                    $TIMSRC::enable(apb);
+                    $TIMSRC::reset(apb);
 
+                    // only counter overflows raise the update flag, not UG
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
                     let mut t = Timer { clocks,
                                         tim,
                                         timeout: Hertz(0),
@@ -420,81 +545,14 @@ macro_rules! tim {
                 pub fn free(self, apb: &mut rcc::$apb) -> $TIMSRC {
                     // pause counter and disable peripheral
                     self.tim.cr1.modify(|_, w| w.cen().clear_bit());
-                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
-                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
-                    apb.enr().modify(|_, w| w.$timXen().disabled());
+                    $TIMSRC::reset(apb);
+                    $TIMSRC::disable(apb);
 
                     self.tim
                 }
             }
 
-            impl<PS: PwmState> Timer<PS> {
-                /// Stop timer and reset frequency (doesn't start/enable)
-                pub fn reset<T>(&mut self, timeout: T)
-                    where T: Into<Hertz<u32>>
-                {
-                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
-                    // restart counter
-                    self.tim.cnt.reset();
-                    let timeout = timeout.into();
-                    let frequency = timeout.0;
-                    let mult = if self.clocks.ppre1() == 1 { 1 } else { 2 };
-                    let ticks = self.clocks.pclk1().0 * mult / frequency;
-                    let psc = u16((ticks - 1) / (1 << 16)).unwrap();
-                    self.tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
-                    let arr = u16(ticks / u32(psc + 1)).unwrap();
-                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
-                    self.timeout = timeout;
-                }
-
-                /// Starts listening for an `event`
-                pub fn listen(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().set_bit());
-                        }
-                    }
-                }
-
-                /// Stops listening for an `event`
-                pub fn unlisten(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().clear_bit());
-                        }
-                    }
-                }
-
-                /// Enable timer
-                pub fn enable(&mut self) {
-                    // enable counter
-                    self.tim.cr1.modify(|_, w| w.cen().bit(true));
-                }
-            }
-
-            impl<PS: PwmState> Periodic for Timer<PS> {}
-
-            impl<PS: PwmState> CountDown for Timer<PS> {
-                type Time = Hertz<u32>;
-
-                fn start<T>(&mut self, timeout: T)
-                    where T: Into<Hertz<u32>>
-                {
-                    self.reset(timeout);
-                    self.enable();
-                }
-
-                fn wait(&mut self) -> nb::Result<(), Void> {
-                    if self.tim.sr.read().uif().bit_is_clear() {
-                        Err(nb::Error::WouldBlock)
-                    } else {
-                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
-                        Ok(())
-                    }
-                }
-            }
+            count_down!(impl<PS: PwmState> Timer<PS>);
 
             impl Timer<PwmFree> {
                 /// Consumes timer and returns pwm channels and timer without
@@ -543,10 +601,127 @@ macro_rules! tim {
     };
 }
 
-tim!(TIM2, APB1, tim2, tim2en, tim2rst);
-tim!(TIM3, APB1, tim3, tim3en, tim3rst);
-tim!(TIM4, APB1, tim4, tim4en, tim4rst);
+/// Trigger output (TRGO) of a basic timer, e.g. to trigger the DAC or ADC
+pub enum MasterMode {
+    /// The UG bit of the EGR register
+    Reset,
+    /// The counter enable signal
+    Enable,
+    /// The update event
+    Update,
+}
+
+macro_rules! basic_tim {
+    ($TIMSRC:ident, $apb:ident, $timmod:ident) => {
+        /// $TIMSRC impl, a basic timer without capture/compare channels
+        pub mod $timmod {
+            use super::*;
+            use crate::pac::$TIMSRC;
+            use crate::rcc;
+            use crate::rcc::{Clocks, Enable, Reset};
+
+            /// Timer impl
+            pub struct Timer {
+                clocks: Clocks,
+                tim: $TIMSRC,
+                timeout: Hertz<u32>,
+            }
+
+            impl Timer {
+                /// Creates new timer
+                pub fn new<T>(tim: $TIMSRC,
+                              timeout: T,
+                              clocks: Clocks,
+                              apb: &mut rcc::$apb)
+                              -> Timer
+                    where T: Into<Hertz<u32>>
+                {
+                    // enable and reset peripheral to a clean slate state
+                    $TIMSRC::enable(apb);
+                    $TIMSRC::reset(apb);
+
+                    // only counter overflows raise the update flag, not UG
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    let mut t = Timer { clocks,
+                                        tim,
+                                        timeout: Hertz(0) };
+                    t.reset(timeout);
+
+                    t
+                }
+
+                /// Releases the TIM peripheral
+                pub fn free(self, apb: &mut rcc::$apb) -> $TIMSRC {
+                    // pause counter and disable peripheral
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    $TIMSRC::reset(apb);
+                    $TIMSRC::disable(apb);
+
+                    self.tim
+                }
+
+                /// Selects the signal sent on the trigger output (TRGO)
+                pub fn set_master_mode(&mut self, mode: MasterMode) {
+                    let mms = match mode {
+                        MasterMode::Reset => 0b000,
+                        MasterMode::Enable => 0b001,
+                        MasterMode::Update => 0b010,
+                    };
+                    self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(mms) });
+                }
+            }
+
+            count_down!(impl<> Timer);
+        }
+    };
+}
+
+tim!(TIM2, APB1, tim2);
+tim!(TIM3, APB1, tim3);
+tim!(TIM4, APB1, tim4);
+basic_tim!(TIM6, APB1, tim6);
+#[cfg(feature = "stm32f303")]
+basic_tim!(TIM7, APB1, tim7);
 // TODO: add other timers with different number of channels
-// tim!(TIM6, APB1, tim6, tim6en, tim6rst);
-// tim!(TIM7, APB1, tim7, tim7en, tim7rst);
-// tim!(TIM8, APB2, tim8, tim8en, tim8rst);
+// tim!(TIM8, APB2, tim8);
+
+#[cfg(test)]
+mod tests {
+    use super::psc_arr;
+
+    #[test]
+    fn psc_arr_range() {
+        let timclks =
+            [1_000_000, 8_000_000, 36_000_000, 64_000_000, 72_000_000];
+        for &timclk in &timclks {
+            let frequencies = (1..=2000).chain((1..=64).map(|n| timclk / n))
+                                        .chain(timclk - 1..=timclk + 1)
+                                        .chain(Some(u32::MAX));
+            for frequency in frequencies {
+                let (psc, arr) = psc_arr(timclk, frequency);
+                let (psc, arr) = (u32::from(psc), u32::from(arr));
+                let ticks = (timclk / frequency).max(2);
+
+                assert!(arr >= 1, "{} Hz / {} Hz", timclk, frequency);
+                // as close as the prescaler allows, never slower
+                let period = (psc + 1) * (arr + 1);
+                assert!(period <= ticks && ticks - period <= psc,
+                        "{} Hz / {} Hz: {} x {}",
+                        timclk,
+                        frequency,
+                        psc + 1,
+                        arr + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn psc_arr_exact() {
+        assert_eq!(psc_arr(8_000_000, 1_000), (0, 7_999));
+        assert_eq!(psc_arr(72_000_000, 1), (1098, 65513));
+        assert_eq!(psc_arr(8_000_000, 4_000_000), (0, 1));
+        // faster than the timer can go
+        assert_eq!(psc_arr(8_000_000, 8_000_000), (0, 1));
+        assert_eq!(psc_arr(8_000_000, 10_000_000), (0, 1));
+    }
+}